@@ -1939,7 +1939,10 @@ impl ParsedFile {
                             );
                         }
                     }
-                    if matches!(kind, NamedDefKind::Let | NamedDefKind::Var) {
+                    if matches!(
+                        kind,
+                        NamedDefKind::Register | NamedDefKind::Let | NamedDefKind::Var
+                    ) {
                         if let Some(ty) = ty {
                             parsed.typed_bindings.push(TypedBinding {
                                 name: name.0.clone(),
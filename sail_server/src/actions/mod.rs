@@ -1,3 +1,4 @@
+use crate::formatting::format_document_edits;
 use crate::state::File;
 use std::collections::HashMap;
 use tower_lsp::lsp_types::{
@@ -181,6 +182,21 @@ pub(crate) fn unused_variable_fix(
     ))
 }
 
+/// Quick fix for `unformatted`: format the whole document with the same
+/// `options` the check used.
+pub(crate) fn format_document_fix(
+    file: &File,
+    diagnostic: &Diagnostic,
+    options: &FormattingOptions,
+) -> Option<(String, TextEdit, bool)> {
+    match diagnostic.code.as_ref()? {
+        tower_lsp::lsp_types::NumberOrString::String(s) if s == "unformatted" => {}
+        _ => return None,
+    }
+    let edit = format_document_edits(file, options)?.pop()?;
+    Some(("Format document".to_string(), edit, true))
+}
+
 pub(crate) fn var_to_let_fix(file: &File, diagnostic: &Diagnostic) -> Option<(String, TextEdit, bool)> {
     let code_str = match diagnostic.code.as_ref()? {
         tower_lsp::lsp_types::NumberOrString::String(s) => s.as_str(),
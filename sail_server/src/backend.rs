@@ -1,9 +1,20 @@
+use crate::actions::default_code_action_format_options;
+use crate::config::{Config, DiagnosticsScope};
+use crate::diagnostics::{diagnostics_for_file, DefinitionIndex};
+use crate::hover::expr_type_in_range;
 use crate::state::{scan_folders, File, Files};
-use std::collections::hash_map::HashMap;
+use crate::symbols::references::ResolvedSymbol;
+use crate::symbols::{rename_preview, resolve_symbol_at, symbol_index_dump};
+use serde::Deserialize;
+use std::collections::{hash_map::HashMap, HashSet};
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tokio::sync::{oneshot, RwLock};
-use tower_lsp::lsp_types::{MessageType, SemanticTokens, Url};
+use tower_lsp::lsp_types::notification::Notification;
+use tower_lsp::lsp_types::{
+    ClientCapabilities, Diagnostic, FormattingOptions, MessageType, Range, SemanticTokens,
+    TextDocumentIdentifier, TextDocumentPositionParams, Url,
+};
 use tower_lsp::Client;
 
 #[derive(Default)]
@@ -17,6 +28,81 @@ pub(crate) struct State {
     /// new typecheck is scheduled so that stale in-flight workers can detect
     /// they have been superseded and bail out early.
     pub(crate) typecheck_generation: HashMap<Url, u64>,
+    /// URIs whose last published diagnostics were non-empty, so they can be
+    /// cleared when the document goes away.
+    pub(crate) published_diagnostics: HashSet<Url>,
+    /// Open documents whose text no longer matches the client's, after a
+    /// change batch was rejected. Range edits to them are ignored, and they
+    /// get no diagnostics, until a full-text change or didOpen.
+    pub(crate) out_of_sync: HashSet<Url>,
+    pub(crate) config: Config,
+    /// Whether the client accepts snippet syntax in completion items.
+    pub(crate) snippet_support: bool,
+    /// Whether the client shows `labelDetails` on completion items.
+    pub(crate) label_details_support: bool,
+    /// Whether the client accepts versioned `documentChanges` in workspace edits.
+    pub(crate) document_changes_support: bool,
+    pub(crate) refresh_support: RefreshSupport,
+    /// Options from the client's last formatting request, which the
+    /// `unformatted` check and its fix go by.
+    pub(crate) format_options: Option<FormattingOptions>,
+}
+
+/// Workspace-wide refresh requests the client accepts, sent once a workspace
+/// scan changes the set of indexed symbols.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) struct RefreshSupport {
+    pub(crate) semantic_tokens: bool,
+    pub(crate) inlay_hints: bool,
+    pub(crate) code_lens: bool,
+    pub(crate) diagnostics: bool,
+}
+
+impl RefreshSupport {
+    pub(crate) fn from_capabilities(capabilities: &ClientCapabilities) -> Self {
+        let Some(workspace) = capabilities.workspace.as_ref() else {
+            return Self::default();
+        };
+        Self {
+            semantic_tokens: workspace
+                .semantic_tokens
+                .as_ref()
+                .and_then(|caps| caps.refresh_support)
+                .unwrap_or(false),
+            inlay_hints: workspace
+                .inlay_hint
+                .as_ref()
+                .and_then(|caps| caps.refresh_support)
+                .unwrap_or(false),
+            code_lens: workspace
+                .code_lens
+                .as_ref()
+                .and_then(|caps| caps.refresh_support)
+                .unwrap_or(false),
+            diagnostics: workspace
+                .diagnostic
+                .as_ref()
+                .and_then(|caps| caps.refresh_support)
+                .unwrap_or(false),
+        }
+    }
+}
+
+/// Custom `sail-lsp/indexed` notification, sent after a workspace scan has
+/// been applied so clients can re-request symbols. Params: `{ "files": number }`.
+pub(crate) enum IndexedNotification {}
+
+impl Notification for IndexedNotification {
+    type Params = serde_json::Value;
+    const METHOD: &'static str = "sail-lsp/indexed";
+}
+
+/// Params of the custom `sail-lsp/typeOf` request.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TypeOfParams {
+    pub(crate) text_document: TextDocumentIdentifier,
+    pub(crate) range: Range,
 }
 
 impl State {
@@ -27,6 +113,67 @@ impl State {
         *gen
     }
 
+    /// Remember whether `uri` is being published with any diagnostics.
+    pub(crate) fn record_published(&mut self, uri: &Url, diagnostics: &[Diagnostic]) {
+        if diagnostics.is_empty() {
+            self.published_diagnostics.remove(uri);
+        } else {
+            self.published_diagnostics.insert(uri.clone());
+        }
+    }
+
+    /// Workspace-wide definition index for cross-file checks, if enabled.
+    /// Built from every file, so build it once per diagnostics pass.
+    pub(crate) fn definition_index(&self) -> Option<DefinitionIndex> {
+        self.config
+            .duplicate_definitions
+            .then(|| DefinitionIndex::new(self.all_files()))
+    }
+
+    /// Whether the `diagnostics.scope` setting covers `uri`.
+    pub(crate) fn reports_diagnostics_for(&self, uri: &Url) -> bool {
+        if self.out_of_sync.contains(uri) {
+            return false;
+        }
+        match self.config.diagnostics_scope {
+            DiagnosticsScope::Workspace => true,
+            // Untitled buffers have no folder but are the user's own code.
+            DiagnosticsScope::Project => {
+                uri.scheme() != "file" || self.disk_files.is_in_folders(uri)
+            }
+            DiagnosticsScope::Open => self.open_files.contains_key(uri),
+        }
+    }
+
+    /// Diagnostics to publish for an open document, given the pass's
+    /// `definition_index`.
+    pub(crate) fn open_file_diagnostics(
+        &self,
+        uri: &Url,
+        index: Option<&DefinitionIndex>,
+    ) -> Option<Vec<Diagnostic>> {
+        let file = self.open_files.get(uri)?;
+        if !self.reports_diagnostics_for(uri) {
+            return Some(Vec::new());
+        }
+        let format_options = self.format_options();
+        Some(diagnostics_for_file(
+            uri,
+            file,
+            index,
+            &self.config.diagnostic_overrides,
+            self.config.format_check.then_some(&format_options),
+        ))
+    }
+
+    /// The options the client last formatted with, or the code action
+    /// defaults before it has asked.
+    pub(crate) fn format_options(&self) -> FormattingOptions {
+        self.format_options
+            .clone()
+            .unwrap_or_else(default_code_action_format_options)
+    }
+
     /// Look up a file by URI, preferring open files over disk files.
     pub(crate) fn get_file(&self, uri: &Url) -> Option<&File> {
         self.open_files
@@ -42,6 +189,32 @@ impl State {
                 .filter(|(uri, _)| !self.open_files.contains_key(uri)),
         )
     }
+
+    /// Have every file check its `$include` targets again.
+    pub(crate) fn forget_include_checks(&self) {
+        for (_, file) in self.open_files.iter().chain(self.disk_files.all_files()) {
+            file.forget_include_checks();
+        }
+    }
+
+    /// All files except those indexed from Sail's library, for requests that
+    /// edit what they find.
+    pub(crate) fn user_files(&self) -> impl Iterator<Item = (&Url, &File)> {
+        self.all_files()
+            .filter(|(uri, _)| !self.disk_files.is_library_file(uri))
+    }
+
+    /// Whether `symbol`, found in `uri`, is defined in Sail's library, so
+    /// renaming it would mean editing the installed library sources.
+    pub(crate) fn is_library_symbol(&self, uri: &Url, symbol: &ResolvedSymbol) -> bool {
+        if self.disk_files.is_library_file(uri) {
+            return true;
+        }
+        symbol.scope != Some(sail_parser::Scope::Local)
+            && self.all_files().any(|(uri, file)| {
+                self.disk_files.is_library_file(uri) && file.definitions.contains_key(&symbol.name)
+            })
+    }
 }
 
 pub(crate) struct Backend {
@@ -129,6 +302,7 @@ pub(crate) const SAIL_BUILTINS: &[&str] = &[
 
 const DIAGNOSTIC_DEBOUNCE_MS: u64 = 250;
 const TYPECHECK_DEBOUNCE_MS: u64 = 250;
+const WORKSPACE_SCAN_DEBOUNCE_MS: u64 = 250;
 const TYPECHECK_MAX_SOURCE_BYTES: usize = 128 * 1024;
 // Sail type inference still recurses much more deeply than the default async
 // worker stack, and even exceeded rust-analyzer's 8 MiB worker size on large
@@ -151,6 +325,22 @@ impl Backend {
         }
     }
 
+    /// Keep the `options` of a formatting request for the `unformatted`
+    /// check, rechecking open documents when they change.
+    pub(crate) async fn remember_format_options(&self, options: &FormattingOptions) {
+        let recheck = {
+            let mut state = self.state.write().await;
+            if state.format_options.as_ref() == Some(options) {
+                return;
+            }
+            state.format_options = Some(options.clone());
+            state.config.format_check
+        };
+        if recheck {
+            self.republish_diagnostics().await;
+        }
+    }
+
     /// Helper for read-only handlers that need a file by URI.
     pub(crate) async fn with_file<F, R>(&self, uri: &Url, f: F) -> tower_lsp::jsonrpc::Result<Option<R>>
     where
@@ -163,20 +353,74 @@ impl Backend {
         Ok(f(&state, file))
     }
 
+    /// Custom `sail-lsp/dumpSymbols` request for inspecting the symbol index.
+    ///
+    /// Params: `{ "query"?: string }`. The result maps each symbol name to a
+    /// list of `{ uri, range, kind }` entries; `query` keeps only names that
+    /// contain it (case-insensitive).
+    pub(crate) async fn dump_symbols(
+        &self,
+        params: serde_json::Value,
+    ) -> tower_lsp::jsonrpc::Result<serde_json::Value> {
+        let query = params.get("query").and_then(serde_json::Value::as_str);
+        let state = self.state.read().await;
+        Ok(symbol_index_dump(state.all_files(), query))
+    }
+
+    /// Custom `sail-lsp/renamePreview` request: the occurrences a rename at
+    /// the given position would change, without changing anything.
+    ///
+    /// Params are `TextDocumentPositionParams`. The result is a list of
+    /// `{ uri, range, line }` entries, or `null` when nothing there can be
+    /// renamed.
+    pub(crate) async fn rename_preview(
+        &self,
+        params: TextDocumentPositionParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<serde_json::Value>> {
+        let uri = &params.text_document.uri;
+        let state = self.state.read().await;
+        let Some(file) = state.get_file(uri) else {
+            return Ok(None);
+        };
+        let Some(symbol) = resolve_symbol_at(file, params.position) else {
+            return Ok(None);
+        };
+        Ok(Some(rename_preview(state.user_files(), uri, &symbol)))
+    }
+
+    /// Custom `sail-lsp/typeOf` request: the type of a selected expression.
+    ///
+    /// The result is the type as Sail source text, or `null` when the
+    /// selection is not an expression with a known type.
+    pub(crate) async fn type_of(
+        &self,
+        params: TypeOfParams,
+    ) -> tower_lsp::jsonrpc::Result<Option<String>> {
+        let uri = &params.text_document.uri;
+        let state = self.state.read().await;
+        let Some(file) = state.get_file(uri) else {
+            return Ok(None);
+        };
+        let files = state.all_files().collect::<Vec<_>>();
+        Ok(expr_type_in_range(&files, uri, file, params.range))
+    }
+
     pub(crate) fn schedule_debounced_diagnostics(&self, uri: Url, version: i32) {
         let state = self.state.clone();
         let client = self.client.clone();
         tokio::spawn(async move {
             tokio::time::sleep(Duration::from_millis(DIAGNOSTIC_DEBOUNCE_MS)).await;
             let diagnostics = {
-                let state_guard = state.read().await;
+                let mut state_guard = state.write().await;
                 if state_guard.diagnostic_versions.get(&uri).copied() != Some(version) {
                     return;
                 }
-                state_guard
-                    .open_files
-                    .get(&uri)
-                    .map(|file| file.lsp_diagnostics())
+                let index = state_guard.definition_index();
+                let diagnostics = state_guard.open_file_diagnostics(&uri, index.as_ref());
+                if let Some(diagnostics) = &diagnostics {
+                    state_guard.record_published(&uri, diagnostics);
+                }
+                diagnostics
             };
             if let Some(diagnostics) = diagnostics {
                 client.publish_diagnostics(uri, diagnostics, None).await;
@@ -184,50 +428,106 @@ impl Backend {
         });
     }
 
-    pub(crate) async fn schedule_workspace_scan(&self) {
-        let (generation, folders) = {
+    /// Republish diagnostics for every open document and ask pulling clients
+    /// to re-request the rest, after something they depend on changed.
+    pub(crate) async fn republish_diagnostics(&self) {
+        let (published, refresh) = {
             let mut state = self.state.write().await;
-            state.disk_scan_generation += 1;
-            (
-                state.disk_scan_generation,
-                state.disk_files.folders().clone(),
-            )
+            let open = state.open_files.keys().cloned().collect::<Vec<_>>();
+            let index = state.definition_index();
+            let mut published = Vec::new();
+            for uri in open {
+                if let Some(diagnostics) = state.open_file_diagnostics(&uri, index.as_ref()) {
+                    state.record_published(&uri, &diagnostics);
+                    published.push((uri, diagnostics));
+                }
+            }
+            (published, state.refresh_support.diagnostics)
         };
 
+        for (uri, diagnostics) in published {
+            self.client
+                .publish_diagnostics(uri, diagnostics, None)
+                .await;
+        }
+        if refresh {
+            let _ = self.client.workspace_diagnostic_refresh().await;
+        }
+    }
+
+    /// Start a new scan generation, superseding any scan in flight, and
+    /// return it with what the scan should walk.
+    async fn begin_workspace_scan(&self) -> (u64, HashSet<Url>, HashSet<Url>, bool) {
+        let mut state = self.state.write().await;
+        state.disk_scan_generation += 1;
+        (
+            state.disk_scan_generation,
+            state
+                .disk_files
+                .scan_roots(state.config.index_library, state.config.lazy_index),
+            state.disk_files.shallow_folders().clone(),
+            state.config.follow_symlinks,
+        )
+    }
+
+    pub(crate) async fn schedule_workspace_scan(&self) {
+        let (generation, folders, shallow, follow_symlinks) = self.begin_workspace_scan().await;
         let state = self.state.clone();
         let client = self.client.clone();
         tokio::spawn(async move {
-            let files = match tokio::task::spawn_blocking(move || scan_folders(folders)).await {
-                Ok(files) => files,
-                Err(err) => {
-                    client
-                        .log_message(
-                            MessageType::ERROR,
-                            format!("workspace scan task failed: {err}"),
-                        )
-                        .await;
-                    return;
-                }
-            };
-
-            let applied = {
-                let mut state_guard = state.write().await;
-                if state_guard.disk_scan_generation != generation {
-                    false
-                } else {
-                    state_guard.disk_files.update(files);
-                    true
-                }
-            };
-
-            if applied {
-                client
-                    .log_message(MessageType::INFO, "workspace scan completed")
-                    .await;
+            // Watched-file bursts (a branch switch, say) each ask for a scan;
+            // only the last one in the window runs.
+            tokio::time::sleep(Duration::from_millis(WORKSPACE_SCAN_DEBOUNCE_MS)).await;
+            if state.read().await.disk_scan_generation != generation {
+                return;
             }
+            run_workspace_scan(state, client, generation, folders, shallow, follow_symlinks).await;
         });
     }
 
+    /// Custom `sail-lsp/reindex` request: rescan the workspace now and wait
+    /// for it to finish.
+    ///
+    /// Params are ignored. The result is `{ files, symbols, elapsedMs }`:
+    /// the indexed files, the top-level definitions in them, and how long the
+    /// scan took.
+    pub(crate) async fn reindex(
+        &self,
+        _params: serde_json::Value,
+    ) -> tower_lsp::jsonrpc::Result<serde_json::Value> {
+        let started = Instant::now();
+        let (generation, folders, shallow, follow_symlinks) = self.begin_workspace_scan().await;
+        run_workspace_scan(
+            self.state.clone(),
+            self.client.clone(),
+            generation,
+            folders,
+            shallow,
+            follow_symlinks,
+        )
+        .await;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+
+        let state = self.state.read().await;
+        let mut files = 0;
+        let mut symbols = 0;
+        for (_, file) in state.disk_files.all_files() {
+            files += 1;
+            symbols += file.parsed().map_or(0, |parsed| {
+                parsed
+                    .decls
+                    .iter()
+                    .filter(|decl| decl.scope == sail_parser::Scope::TopLevel)
+                    .count()
+            });
+        }
+        Ok(serde_json::json!({
+            "files": files,
+            "symbols": symbols,
+            "elapsedMs": elapsed_ms,
+        }))
+    }
+
     pub(crate) fn schedule_debounced_typecheck(&self, uri: Url, version: i32, file: File) {
         let state = self.state.clone();
         let client = self.client.clone();
@@ -273,15 +573,23 @@ impl Backend {
                     let _ = tx.send(file);
                 });
 
-            let updated_file = match spawn_result {
-                Ok(_handle) => rx.await.ok(),
-                Err(err) => {
+            // A failed or crashed worker leaves the previous diagnostics in
+            // place; tell the user instead of silently showing stale results.
+            let failure = match spawn_result {
+                Ok(_handle) => match rx.await {
+                    Ok(updated) => Ok(updated),
+                    Err(_) => Err("typecheck worker exited without a result".to_string()),
+                },
+                Err(err) => Err(format!("failed to spawn typecheck worker: {err}")),
+            };
+            let updated_file = match failure {
+                Ok(updated) => Some(updated),
+                Err(message) => {
+                    let message = format!("{message}; diagnostics for {uri} may be stale");
                     client
-                        .log_message(
-                            MessageType::ERROR,
-                            format!("failed to spawn typecheck worker: {err}"),
-                        )
+                        .log_message(MessageType::ERROR, message.clone())
                         .await;
+                    client.show_message(MessageType::ERROR, message).await;
                     None
                 }
             };
@@ -302,7 +610,12 @@ impl Backend {
                 if let Some(updated) = updated_file {
                     *file = updated;
                 }
-                Some(file.lsp_diagnostics())
+                let index = state_guard.definition_index();
+                let diagnostics = state_guard.open_file_diagnostics(&uri, index.as_ref());
+                if let Some(diagnostics) = &diagnostics {
+                    state_guard.record_published(&uri, diagnostics);
+                }
+                diagnostics
             };
 
             if let Some(diagnostics) = diagnostics {
@@ -311,3 +624,65 @@ impl Backend {
         });
     }
 }
+
+/// Walk `folders`, not descending into those in `shallow`, and, unless a newer scan has started meanwhile, replace the
+/// indexed disk files with the result and tell the client the index changed.
+async fn run_workspace_scan(
+    state: Arc<RwLock<State>>,
+    client: Client,
+    generation: u64,
+    folders: HashSet<Url>,
+    shallow: HashSet<Url>,
+    follow_symlinks: bool,
+) {
+    let scan = move || scan_folders(folders, &shallow, follow_symlinks);
+    let files = match tokio::task::spawn_blocking(scan).await {
+        Ok(files) => files,
+        Err(err) => {
+            client
+                .log_message(
+                    MessageType::ERROR,
+                    format!("workspace scan task failed: {err}"),
+                )
+                .await;
+            return;
+        }
+    };
+
+    let applied = {
+        let mut state_guard = state.write().await;
+        if state_guard.disk_scan_generation != generation {
+            None
+        } else {
+            let file_count = files.len();
+            state_guard.disk_files.update(files);
+            Some((file_count, state_guard.refresh_support))
+        }
+    };
+
+    let Some((file_count, refresh)) = applied else {
+        return;
+    };
+    client
+        .log_message(MessageType::INFO, "workspace scan completed")
+        .await;
+    client
+        .send_notification::<IndexedNotification>(serde_json::json!({
+            "files": file_count,
+        }))
+        .await;
+    // Views computed against the old index are stale now. Failed
+    // refreshes only mean the client keeps what it has.
+    if refresh.semantic_tokens {
+        let _ = client.semantic_tokens_refresh().await;
+    }
+    if refresh.inlay_hints {
+        let _ = client.inlay_hint_refresh().await;
+    }
+    if refresh.code_lens {
+        let _ = client.code_lens_refresh().await;
+    }
+    if refresh.diagnostics {
+        let _ = client.workspace_diagnostic_refresh().await;
+    }
+}
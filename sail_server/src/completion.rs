@@ -1,7 +1,18 @@
+use crate::backend::{SAIL_BUILTINS, SAIL_KEYWORDS};
+use crate::hover::infer_expr_type_text;
 use crate::state::File;
-use crate::symbols::{builtin_docs, extract_comments, function_snippet, Parameter};
-use std::collections::{BTreeMap, HashMap};
-use tower_lsp::lsp_types::{CompletionItem, CompletionItemKind, InsertTextFormat, Url};
+use crate::symbols::analysis::CallableSignature;
+use crate::symbols::{
+    builtin_docs, enclosing_item_span, extract_comments, function_snippet, Parameter,
+};
+use sail_parser::core_ast::{DefinitionKind, NamedDefDetail, UnionPayload};
+use sail_parser::{Span, Token};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use tower_lsp::lsp_types::{
+    CompletionItem, CompletionItemKind, CompletionItemLabelDetails, CompletionTextEdit,
+    InsertTextFormat, Range, TextEdit, Url,
+};
 
 #[derive(Clone)]
 struct CompletionCandidate {
@@ -14,6 +25,105 @@ fn is_identifier_byte(byte: u8) -> bool {
     byte.is_ascii_alphanumeric() || matches!(byte, b'_' | b'?' | b'\'' | b'~')
 }
 
+/// Turn function call snippets back into bare names, for clients without
+/// snippet support or users who turned call parentheses off.
+pub(crate) fn plain_call_completions(items: &mut [CompletionItem]) {
+    for item in items {
+        if matches!(
+            item.kind,
+            Some(CompletionItemKind::FUNCTION | CompletionItemKind::METHOD)
+        ) && item.insert_text_format == Some(InsertTextFormat::SNIPPET)
+        {
+            item.insert_text = Some(item.label.clone());
+            item.insert_text_format = Some(InsertTextFormat::PLAIN_TEXT);
+        }
+    }
+}
+
+/// Fill in `label_details` for clients that show them: a callable's
+/// signature right after its name, and on the right the defining file's name,
+/// unless the item is already marked as coming from the library.
+pub(crate) fn add_completion_label_details<'a, I>(items: &mut [CompletionItem], files: I)
+where
+    I: IntoIterator<Item = (&'a Url, &'a File)>,
+{
+    let mut files = files.into_iter().collect::<Vec<_>>();
+    files.sort_by_key(|&(uri, _)| uri);
+
+    let mut signatures: HashMap<&str, (&CallableSignature, &Url)> = HashMap::new();
+    let mut defined_in: HashMap<&str, &Url> = HashMap::new();
+    for (uri, file) in &files {
+        for sig in file.signature_index.values() {
+            // Prefer val specs, as the signature index does per file.
+            let is_val = sig.label.starts_with("val");
+            signatures
+                .entry(sig.name.as_str())
+                .and_modify(|existing| {
+                    if is_val && !existing.0.label.starts_with("val") {
+                        *existing = (sig, uri);
+                    }
+                })
+                .or_insert((sig, uri));
+        }
+        if let Some(parsed) = file.parsed() {
+            for decl in &parsed.decls {
+                if decl.scope == sail_parser::Scope::TopLevel {
+                    defined_in.entry(decl.name.as_str()).or_insert(uri);
+                }
+            }
+        }
+    }
+
+    for item in items {
+        if item.kind == Some(CompletionItemKind::KEYWORD)
+            || matches!(item.detail.as_deref(), Some("binding" | "type parameter"))
+        {
+            continue;
+        }
+        let label = item.label.as_str();
+        let signature = signatures.get(label).filter(|_| {
+            matches!(
+                item.kind,
+                Some(CompletionItemKind::FUNCTION | CompletionItemKind::METHOD)
+            )
+        });
+        let Some(uri) = signature
+            .map(|(_, uri)| *uri)
+            .or_else(|| defined_in.get(label).copied())
+        else {
+            continue;
+        };
+        let description = item
+            .label_details
+            .take()
+            .and_then(|details| details.description)
+            .or_else(|| {
+                uri.path_segments()
+                    .and_then(|mut segments| segments.next_back())
+                    .map(str::to_string)
+            });
+        item.label_details = Some(CompletionItemLabelDetails {
+            detail: signature.and_then(|(sig, _)| signature_after_name(sig)),
+            description,
+        });
+    }
+}
+
+/// The part of a signature label after the callable's name, on one line:
+/// ` : (int, int) -> int` for `val add : (int, int) -> int`.
+fn signature_after_name(sig: &CallableSignature) -> Option<String> {
+    let (_, rest) = sig.label.split_once(char::is_whitespace)?;
+    let rest = rest.trim_start().strip_prefix(sig.name.as_str())?;
+    let rest = rest.split_whitespace().collect::<Vec<_>>().join(" ");
+    if rest.is_empty() {
+        None
+    } else if rest.starts_with('(') {
+        Some(rest)
+    } else {
+        Some(format!(" {rest}"))
+    }
+}
+
 pub(crate) fn completion_prefix(text: &str, offset: usize) -> &str {
     let offset = offset.min(text.len());
     let bytes = text.as_bytes();
@@ -26,6 +136,140 @@ pub(crate) fn completion_prefix(text: &str, offset: usize) -> &str {
     &text[start..offset]
 }
 
+/// Whether `offset` follows the decimal point of a number, as in `3.` or
+/// `3.14`, where the `.` trigger should not offer anything.
+pub(crate) fn in_numeric_literal(text: &str, offset: usize) -> bool {
+    let offset = offset.min(text.len());
+    let prefix = completion_prefix(text, offset);
+    if !prefix.bytes().all(|byte| byte.is_ascii_digit()) {
+        return false;
+    }
+    let Some(before_dot) = text[..offset - prefix.len()].strip_suffix('.') else {
+        return false;
+    };
+    let number = completion_prefix(before_dot, before_dot.len());
+    number.starts_with(|c: char| c.is_ascii_digit())
+}
+
+/// Whether `offset` is inside a type: after a `:` or `->`, or on the right of
+/// `type name =`, with no `=`, `;` or brace in between outside parentheses.
+pub(crate) fn in_type_expression(text: &str, offset: usize) -> bool {
+    let bytes = text.as_bytes();
+    let mut depth = 0usize;
+    let mut i = offset.min(text.len());
+    while i > 0 {
+        i -= 1;
+        let prev = i.checked_sub(1).map(|p| bytes[p]);
+        match bytes[i] {
+            b')' | b']' => depth += 1,
+            b'(' | b'[' => depth = depth.saturating_sub(1),
+            _ if depth > 0 => {}
+            b':' if prev != Some(b':') && bytes.get(i + 1) != Some(&b':') => return true,
+            b'>' if prev == Some(b'-') => return true,
+            b'>' if prev == Some(b'=') => return false,
+            b'=' if matches!(prev, Some(b'=' | b'<' | b'>' | b'!'))
+                || bytes.get(i + 1) == Some(&b'=') => {}
+            b'=' => {
+                let line_start = text[..i].rfind('\n').map_or(0, |at| at + 1);
+                return text[line_start..i].trim_start().starts_with("type ");
+            }
+            b';' | b'{' | b'}' => return false,
+            _ => {}
+        }
+    }
+    false
+}
+
+/// Type-level constants such as `type xlen : Int = 64`, with their kind and
+/// value as detail.
+fn type_level_constants(file: &File) -> Vec<(String, String)> {
+    let Some(ast) = file.core_ast() else {
+        return Vec::new();
+    };
+    ast.defs
+        .iter()
+        .filter_map(|(def, _)| {
+            let DefinitionKind::TypeAlias(alias) = &def.kind else {
+                return None;
+            };
+            let kind = alias.kind.as_ref()?;
+            if !matches!(kind.0.as_str(), "Int" | "Bool") {
+                return None;
+            }
+            let target = alias.target.as_ref()?.1;
+            let value = file.source.text().get(target.start..target.end)?;
+            Some((
+                alias.name.0.clone(),
+                format!("{} = {}", kind.0, value.trim()),
+            ))
+        })
+        .collect()
+}
+
+/// Top-level `let` constants, with their value as detail.
+fn top_level_lets(file: &File) -> Vec<(String, String)> {
+    let Some(ast) = file.core_ast() else {
+        return Vec::new();
+    };
+    ast.defs
+        .iter()
+        .filter_map(|(def, _)| {
+            let DefinitionKind::Named(named) = &def.kind else {
+                return None;
+            };
+            if named.kind != sail_parser::core_ast::NamedDefKind::Let {
+                return None;
+            }
+            let value = named
+                .value_span
+                .and_then(|span| file.source.text().get(span.start..span.end))
+                .map_or_else(|| "let".to_string(), |value| format!("= {}", value.trim()));
+            Some((named.name.0.clone(), value))
+        })
+        .collect()
+}
+
+/// Enum members and struct/bitfield fields, with the name of the type that
+/// declares them.
+fn type_members(file: &File) -> Vec<(String, CompletionItemKind, String)> {
+    let Some(ast) = file.core_ast() else {
+        return Vec::new();
+    };
+    let mut members = Vec::new();
+    for (def, _) in &ast.defs {
+        let DefinitionKind::Named(named) = &def.kind else {
+            continue;
+        };
+        let (kind, names) = match &named.detail {
+            Some(NamedDefDetail::Enum { members: cases, .. }) => (
+                CompletionItemKind::ENUM_MEMBER,
+                cases
+                    .iter()
+                    .map(|(member, _)| member.name.0.clone())
+                    .collect::<Vec<_>>(),
+            ),
+            Some(NamedDefDetail::Struct { fields }) => (
+                CompletionItemKind::FIELD,
+                fields
+                    .iter()
+                    .map(|(field, _)| field.name.0.clone())
+                    .collect(),
+            ),
+            Some(NamedDefDetail::Bitfield { fields }) => (
+                CompletionItemKind::FIELD,
+                fields
+                    .iter()
+                    .map(|(field, _)| field.name.0.clone())
+                    .collect(),
+            ),
+            _ => continue,
+        };
+        let parent = &named.name.0;
+        members.extend(names.into_iter().map(|name| (name, kind, parent.clone())));
+    }
+    members
+}
+
 pub(crate) fn completion_trigger_characters() -> Vec<String> {
     vec![
         ".".to_string(),
@@ -37,9 +281,17 @@ pub(crate) fn completion_trigger_characters() -> Vec<String> {
         "'".to_string(),
         "@".to_string(),
         "$".to_string(),
+        "\"".to_string(),
+        "<".to_string(),
+        "/".to_string(),
     ]
 }
 
+/// Trigger characters that only start completion inside an include path.
+pub(crate) fn is_include_path_trigger(trigger: &str) -> bool {
+    matches!(trigger, "\"" | "<" | "/")
+}
+
 fn completion_kind_priority(kind: &CompletionItemKind) -> u8 {
     match kind {
         &CompletionItemKind::KEYWORD => 8,
@@ -54,6 +306,21 @@ fn completion_kind_priority(kind: &CompletionItemKind) -> u8 {
     }
 }
 
+/// Position of a kind in the completion list: symbols the user is likely
+/// reaching for (bindings, then functions) before types, builtins and keywords.
+fn completion_sort_rank(kind: &CompletionItemKind) -> u8 {
+    match kind {
+        &CompletionItemKind::VARIABLE | &CompletionItemKind::FIELD => 0,
+        &CompletionItemKind::FUNCTION | &CompletionItemKind::METHOD => 1,
+        &CompletionItemKind::ENUM_MEMBER => 2,
+        &CompletionItemKind::TYPE_PARAMETER => 3,
+        &CompletionItemKind::ENUM | &CompletionItemKind::CLASS => 4,
+        &CompletionItemKind::CONSTANT => 5,
+        &CompletionItemKind::KEYWORD => 6,
+        _ => 7,
+    }
+}
+
 fn upsert_candidate(
     candidates: &mut BTreeMap<String, CompletionCandidate>,
     label: String,
@@ -88,8 +355,7 @@ pub(crate) fn build_completion_items<'a, I>(
     text: &str,
     offset: usize,
     prefix: &str,
-    keywords: &[&str],
-    builtins: &[&str],
+    is_library: impl Fn(&Url) -> bool,
 ) -> Vec<CompletionItem>
 where
     I: IntoIterator<Item = (&'a Url, &'a File)>,
@@ -112,6 +378,8 @@ where
         }
         depth <= 0
     };
+    let in_type = in_type_expression(text, offset - prefix.len());
+    let after_dot = text[..offset - prefix.len()].ends_with('.');
 
     let mut candidates: BTreeMap<String, CompletionCandidate> = BTreeMap::new();
     let mut call_signatures: HashMap<String, Vec<Parameter>> = HashMap::new();
@@ -123,7 +391,7 @@ where
         }
     }
 
-    for keyword in keywords {
+    for keyword in SAIL_KEYWORDS {
         let is_top_level_kw = matches!(
             *keyword,
             "function" | "val" | "enum" | "struct" | "union" | "type" | "register" | "overload"
@@ -158,7 +426,7 @@ where
         }
     }
 
-    for builtin in builtins {
+    for builtin in SAIL_BUILTINS {
         let kind = if builtin
             .chars()
             .next()
@@ -179,12 +447,27 @@ where
         );
     }
 
+    // A name being typed in its own definition is not a completion of itself.
+    let is_being_defined =
+        |uri: &Url, span: Span| uri == current_uri && span.start <= offset && offset <= span.end;
+    // Names defined only in Sail's library are marked `prelude`.
+    let mut library_names = HashSet::new();
+    let mut project_names = HashSet::new();
+
     for (candidate_uri, candidate_file) in &all_files {
+        let names = if is_library(candidate_uri) {
+            &mut library_names
+        } else {
+            &mut project_names
+        };
         if let Some(parsed) = candidate_file.parsed() {
             for decl in &parsed.decls {
-                if decl.scope != sail_parser::Scope::TopLevel {
+                if decl.scope != sail_parser::Scope::TopLevel
+                    || is_being_defined(candidate_uri, decl.span)
+                {
                     continue;
                 }
+                names.insert(decl.name.clone());
                 let (name, kind, detail) = match decl.kind {
                     sail_parser::DeclKind::Function => (
                         decl.name.clone(),
@@ -232,9 +515,12 @@ where
                     kind,
                     CompletionItemKind::FUNCTION | CompletionItemKind::METHOD
                 ) {
-                    call_signatures
-                        .get(&name)
-                        .map(|params| function_snippet(&name, params))
+                    Some(
+                        call_signatures
+                            .get(&name)
+                            .map(|params| function_snippet(&name, params))
+                            .unwrap_or_else(|| format!("{name}($0)")),
+                    )
                 } else {
                     None
                 };
@@ -249,12 +535,22 @@ where
                 );
             }
             if *candidate_uri == current_uri {
+                // Local bindings are only visible inside the definition that
+                // introduces them, so only offer those from the item under the
+                // cursor.
+                let enclosing_item = enclosing_item_span(candidate_file, offset);
                 for occurrence in &parsed.symbol_occurrences {
                     if occurrence.role.is_none()
                         || occurrence.scope != Some(sail_parser::Scope::Local)
+                        || is_being_defined(candidate_uri, occurrence.span)
                     {
                         continue;
                     }
+                    if !enclosing_item.is_some_and(|item| {
+                        item.start <= occurrence.span.start && occurrence.span.end <= item.end
+                    }) {
+                        continue;
+                    }
                     match occurrence.kind {
                         sail_parser::SymbolOccurrenceKind::Value => {
                             upsert_candidate(
@@ -285,6 +581,66 @@ where
         }
     }
 
+    // Type-level constants are types as far as the declaration index goes;
+    // show them as the numbers they stand for. Values are not types, so
+    // `let` constants, functions and bindings stay out of type positions.
+    for (_, candidate_file) in &all_files {
+        for (name, detail) in type_level_constants(candidate_file) {
+            candidates.insert(
+                name,
+                CompletionCandidate {
+                    kind: CompletionItemKind::CONSTANT,
+                    detail: Some(detail),
+                    snippet: None,
+                },
+            );
+        }
+    }
+    // Fields only make sense after `record.`, enum members wherever a value
+    // can go.
+    for (_, candidate_file) in &all_files {
+        for (name, kind, parent) in type_members(candidate_file) {
+            let wanted = if kind == CompletionItemKind::FIELD {
+                after_dot
+            } else {
+                !in_type && !after_dot
+            };
+            if wanted {
+                upsert_candidate(
+                    &mut candidates,
+                    name,
+                    CompletionCandidate {
+                        kind,
+                        detail: Some(parent),
+                        snippet: None,
+                    },
+                );
+            }
+        }
+    }
+    if in_type {
+        candidates.retain(|_, candidate| {
+            !matches!(
+                candidate.kind,
+                CompletionItemKind::FUNCTION | CompletionItemKind::VARIABLE
+            )
+        });
+    } else {
+        for (_, candidate_file) in &all_files {
+            for (name, detail) in top_level_lets(candidate_file) {
+                upsert_candidate(
+                    &mut candidates,
+                    name,
+                    CompletionCandidate {
+                        kind: CompletionItemKind::CONSTANT,
+                        detail: Some(detail),
+                        snippet: None,
+                    },
+                );
+            }
+        }
+    }
+
     let mut items = candidates
         .into_iter()
         .filter_map(|(label, candidate)| {
@@ -294,7 +650,7 @@ where
                 return None;
             }
 
-            Some((score, completion_kind_priority(&candidate.kind), {
+            Some((score, completion_sort_rank(&candidate.kind), {
                 let insert_text_format = if candidate.snippet.is_some() {
                     InsertTextFormat::SNIPPET
                 } else {
@@ -305,8 +661,15 @@ where
                     .clone()
                     .unwrap_or_else(|| "symbol".to_string());
                 let kind_name = format!("{:?}", candidate.kind);
+                let from_library = candidate.kind != CompletionItemKind::KEYWORD
+                    && library_names.contains(&label)
+                    && !project_names.contains(&label);
                 CompletionItem {
                     label: label.clone(),
+                    label_details: from_library.then(|| CompletionItemLabelDetails {
+                        detail: None,
+                        description: Some("prelude".to_string()),
+                    }),
                     kind: Some(candidate.kind),
                     detail: candidate.detail,
                     filter_text: Some(label.clone()),
@@ -323,14 +686,12 @@ where
         })
         .collect::<Vec<_>>();
 
-    items.sort_by(
-        |(score_a, priority_a, item_a), (score_b, priority_b, item_b)| {
-            score_a
-                .cmp(score_b)
-                .then_with(|| priority_b.cmp(priority_a))
-                .then_with(|| item_a.label.cmp(&item_b.label))
-        },
-    );
+    items.sort_by(|(score_a, rank_a, item_a), (score_b, rank_b, item_b)| {
+        score_a
+            .cmp(score_b)
+            .then_with(|| rank_a.cmp(rank_b))
+            .then_with(|| item_a.label.cmp(&item_b.label))
+    });
 
     const MAX_COMPLETIONS: usize = 200;
     if items.len() > MAX_COMPLETIONS {
@@ -340,8 +701,13 @@ where
     items
         .into_iter()
         .enumerate()
-        .map(|(index, (_, _, mut item))| {
+        .map(|(index, (score, _, mut item))| {
             item.sort_text = Some(format!("{index:04}_{}", item.label.to_ascii_lowercase()));
+            // The best exact match sorts first; select it so accepting the
+            // completion picks what was typed.
+            if index == 0 && score == 0 && !prefix.is_empty() {
+                item.preselect = Some(true);
+            }
             item
         })
         .collect()
@@ -425,6 +791,138 @@ pub(crate) fn postfix_completions(
     items
 }
 
+/// Token indices `(first, last)` of the scrutinee when `offset` sits at an arm
+/// pattern of `match <expr> { ... }`: right after the opening brace or after
+/// the comma that ends the previous arm.
+fn match_scrutinee_tokens(tokens: &[(Token, Span)], offset: usize) -> Option<(usize, usize)> {
+    let before = tokens
+        .iter()
+        .take_while(|(_, span)| span.end <= offset)
+        .count();
+    let last = before.checked_sub(1)?;
+    if !matches!(tokens[last].0, Token::LeftCurlyBracket | Token::Comma) {
+        return None;
+    }
+
+    let mut depth = 0usize;
+    let mut open = None;
+    for idx in (0..=last).rev() {
+        match tokens[idx].0 {
+            Token::RightCurlyBracket | Token::RightBracket | Token::RightSquareBracket => {
+                depth += 1
+            }
+            Token::LeftCurlyBracket if depth == 0 => {
+                open = Some(idx);
+                break;
+            }
+            Token::LeftBracket | Token::LeftSquareBracket if depth == 0 => return None,
+            Token::LeftCurlyBracket | Token::LeftBracket | Token::LeftSquareBracket => depth -= 1,
+            _ => {}
+        }
+    }
+    let open = open?;
+
+    let mut depth = 0usize;
+    for idx in (0..open).rev() {
+        match tokens[idx].0 {
+            Token::RightBracket | Token::RightSquareBracket => depth += 1,
+            Token::LeftBracket | Token::LeftSquareBracket if depth > 0 => depth -= 1,
+            Token::KwMatch if depth == 0 => {
+                return (idx + 1 < open).then_some((idx + 1, open - 1));
+            }
+            Token::LeftCurlyBracket
+            | Token::RightCurlyBracket
+            | Token::Semicolon
+            | Token::LeftBracket
+            | Token::LeftSquareBracket => return None,
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Members of the enum, or variants of the union, named by the head of `ty`.
+fn enum_like_cases(all_files: &[(&Url, &File)], ty: &str) -> Option<(String, Vec<String>)> {
+    let type_name = ty
+        .split(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '\''))
+        .next()
+        .filter(|name| !name.is_empty())?;
+
+    for (_, file) in all_files {
+        let Some(ast) = file.core_ast() else {
+            continue;
+        };
+        for (def, _) in &ast.defs {
+            let DefinitionKind::Named(named) = &def.kind else {
+                continue;
+            };
+            if named.name.0 != type_name {
+                continue;
+            }
+            let cases = match &named.detail {
+                Some(NamedDefDetail::Enum { members, .. }) => members
+                    .iter()
+                    .map(|(member, _)| member.name.0.clone())
+                    .collect(),
+                Some(NamedDefDetail::Union { variants }) => variants
+                    .iter()
+                    .map(|(variant, _)| match variant.payload {
+                        UnionPayload::Type(_) => format!("{}(_)", variant.name.0),
+                        UnionPayload::Struct { .. } => format!("{}(..)", variant.name.0),
+                    })
+                    .collect(),
+                _ => continue,
+            };
+            return Some((type_name.to_string(), cases));
+        }
+    }
+    None
+}
+
+/// Offer the cases of the scrutinee's enum or union type when completing an
+/// arm of a `match`. Empty when the scrutinee type cannot be resolved.
+pub(crate) fn match_arm_completions<'a>(
+    all_files: &[(&'a Url, &'a File)],
+    current_uri: &Url,
+    file: &File,
+    offset: usize,
+    prefix: &str,
+) -> Vec<CompletionItem> {
+    let Some(tokens) = file.tokens.as_deref() else {
+        return Vec::new();
+    };
+    let Some((start, end)) = match_scrutinee_tokens(tokens, offset - prefix.len()) else {
+        return Vec::new();
+    };
+    let Some((type_name, cases)) = sail_parser::parse_expr_fragment(tokens, start, end)
+        .and_then(|expr| infer_expr_type_text(all_files, current_uri, file, &expr))
+        .and_then(|ty| enum_like_cases(all_files, &ty))
+    else {
+        return Vec::new();
+    };
+
+    cases
+        .into_iter()
+        .enumerate()
+        .map(|(idx, pattern)| {
+            let label = pattern.split('(').next().unwrap_or(&pattern).to_string();
+            CompletionItem {
+                kind: Some(CompletionItemKind::ENUM_MEMBER),
+                detail: Some(type_name.clone()),
+                insert_text: (pattern != label).then_some(pattern),
+                sort_text: Some(format!("{idx:04}")),
+                data: Some(serde_json::json!({
+                    "source": "sail-lsp",
+                    "kind": "enum member",
+                    "detail": type_name,
+                })),
+                label,
+                ..CompletionItem::default()
+            }
+        })
+        .collect()
+}
+
 /// Extract the receiver expression text before a dot.
 fn extract_receiver_expr(text: &str, dot_pos: usize) -> &str {
     let bytes = text.as_bytes();
@@ -525,6 +1023,76 @@ pub(crate) fn pragma_completions(text: &str, offset: usize) -> Vec<CompletionIte
         .collect()
 }
 
+/// Path completions inside `$include "..."` (relative to the current file) or
+/// `$include <...>` (relative to Sail's library): `.sail` files and
+/// directories in the folder typed so far. `None` outside an include path.
+pub(crate) fn include_path_completions(
+    uri: &Url,
+    file: &File,
+    offset: usize,
+    library_dir: Option<&Path>,
+) -> Option<Vec<CompletionItem>> {
+    let text = file.source.text();
+    let line_start = text[..offset].rfind('\n').map_or(0, |at| at + 1);
+    let rest = text[line_start..offset]
+        .trim_start()
+        .strip_prefix("$include")?
+        .trim_start();
+    let (close, root) = match rest.chars().next()? {
+        '"' => ('"', uri.to_file_path().ok()?.parent()?.to_path_buf()),
+        '<' => ('>', library_dir?.to_path_buf()),
+        _ => return None,
+    };
+    let typed = &rest[1..];
+    if typed.contains(close) {
+        return None;
+    }
+    let (folder, name_prefix) = typed.rsplit_once('/').unwrap_or(("", typed));
+    let current = uri.to_file_path().ok();
+    let closer = if text[offset..].starts_with(close) {
+        String::new()
+    } else {
+        close.to_string()
+    };
+    let range = Range::new(
+        file.source.position_at(offset - name_prefix.len()),
+        file.source.position_at(offset),
+    );
+
+    let mut items = Vec::new();
+    for entry in std::fs::read_dir(root.join(folder)).into_iter().flatten() {
+        let Ok(entry) = entry else {
+            continue;
+        };
+        let path = entry.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        if !name.starts_with(name_prefix) || name.starts_with('.') {
+            continue;
+        }
+        let (label, kind, new_text) = if path.is_dir() {
+            let label = format!("{name}/");
+            (label.clone(), CompletionItemKind::FOLDER, label)
+        } else if path.extension() == Some("sail".as_ref()) && current.as_ref() != Some(&path) {
+            let new_text = format!("{name}{closer}");
+            (name.to_string(), CompletionItemKind::FILE, new_text)
+        } else {
+            continue;
+        };
+        items.push(CompletionItem {
+            sort_text: Some(label.clone()),
+            filter_text: Some(label.clone()),
+            label,
+            kind: Some(kind),
+            text_edit: Some(CompletionTextEdit::Edit(TextEdit { range, new_text })),
+            ..CompletionItem::default()
+        });
+    }
+    items.sort_by(|a, b| a.label.cmp(&b.label));
+    Some(items)
+}
+
 /// Built-in Sail code snippet templates.
 pub(crate) fn snippet_completions(prefix: &str, is_top_level: bool) -> Vec<CompletionItem> {
     let prefix_lower = prefix.to_ascii_lowercase();
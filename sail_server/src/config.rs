@@ -0,0 +1,162 @@
+//! User settings, taken from `initializationOptions` and
+//! `workspace/didChangeConfiguration`. Both may wrap them in a `sail` section.
+
+use crate::logging::server_log;
+use regex::Regex;
+use serde_json::Value;
+use tower_lsp::lsp_types::DiagnosticSeverity;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Config {
+    /// Complete functions as `name(...)` call snippets rather than bare names.
+    pub(crate) completion_call_parentheses: bool,
+    /// Warn about top-level definitions repeated in another file.
+    pub(crate) duplicate_definitions: bool,
+    /// Which files get diagnostics at all.
+    pub(crate) diagnostics_scope: DiagnosticsScope,
+    /// Per-message severity changes, first match wins.
+    pub(crate) diagnostic_overrides: Vec<DiagnosticOverride>,
+    /// Note open documents that the formatter would change.
+    pub(crate) format_check: bool,
+    /// Walk symlinked directories when scanning workspace folders.
+    pub(crate) follow_symlinks: bool,
+    /// Index Sail's library folder alongside the workspace.
+    pub(crate) index_library: bool,
+    /// Index only the projects of opened files rather than whole workspace
+    /// folders.
+    pub(crate) lazy_index: bool,
+}
+
+/// One `diagnostics.overrides` rule: diagnostics whose message matches the
+/// `message` regex get `severity`, or are dropped when it is `None`
+/// (`"ignore"`).
+#[derive(Clone, Debug)]
+pub(crate) struct DiagnosticOverride {
+    pub(crate) message: Regex,
+    pub(crate) severity: Option<DiagnosticSeverity>,
+}
+
+impl PartialEq for DiagnosticOverride {
+    fn eq(&self, other: &Self) -> bool {
+        self.message.as_str() == other.message.as_str() && self.severity == other.severity
+    }
+}
+
+impl Eq for DiagnosticOverride {}
+
+impl DiagnosticOverride {
+    fn parse(value: &Value) -> Option<Self> {
+        let pattern = value.get("message")?.as_str()?;
+        let severity = match value.get("severity")?.as_str()? {
+            "error" => Some(DiagnosticSeverity::ERROR),
+            "warning" => Some(DiagnosticSeverity::WARNING),
+            "information" => Some(DiagnosticSeverity::INFORMATION),
+            "hint" => Some(DiagnosticSeverity::HINT),
+            "ignore" => None,
+            _ => return None,
+        };
+        let message = match Regex::new(pattern) {
+            Ok(message) => message,
+            Err(err) => {
+                server_log!("ignoring diagnostics.overrides rule `{pattern}`: {err}");
+                return None;
+            }
+        };
+        Some(Self { message, severity })
+    }
+
+    pub(crate) fn matches(&self, message: &str) -> bool {
+        self.message.is_match(message)
+    }
+}
+
+/// Files that diagnostics are reported for (`diagnostics.scope`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DiagnosticsScope {
+    /// Every indexed file, including Sail's library (`"workspace"`).
+    Workspace,
+    /// Files under a workspace folder, and unsaved buffers (`"project"`).
+    Project,
+    /// Only documents open in the editor (`"open"`).
+    Open,
+}
+
+impl DiagnosticsScope {
+    fn parse(value: &str) -> Option<Self> {
+        match value {
+            "workspace" => Some(Self::Workspace),
+            "project" => Some(Self::Project),
+            "open" => Some(Self::Open),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            completion_call_parentheses: true,
+            duplicate_definitions: true,
+            diagnostics_scope: DiagnosticsScope::Workspace,
+            diagnostic_overrides: Vec::new(),
+            format_check: false,
+            follow_symlinks: true,
+            index_library: true,
+            lazy_index: false,
+        }
+    }
+}
+
+impl Config {
+    /// Apply any settings present in `value`, leaving the rest untouched.
+    pub(crate) fn update(&mut self, value: &Value) {
+        let value = value.get("sail").unwrap_or(value);
+        if let Some(enabled) = value
+            .pointer("/completion/callParentheses")
+            .and_then(Value::as_bool)
+        {
+            self.completion_call_parentheses = enabled;
+        }
+        if let Some(enabled) = value
+            .pointer("/diagnostics/duplicateDefinitions")
+            .and_then(Value::as_bool)
+        {
+            self.duplicate_definitions = enabled;
+        }
+        if let Some(scope) = value
+            .pointer("/diagnostics/scope")
+            .and_then(Value::as_str)
+            .and_then(DiagnosticsScope::parse)
+        {
+            self.diagnostics_scope = scope;
+        }
+        if let Some(enabled) = value
+            .pointer("/diagnostics/formatting")
+            .and_then(Value::as_bool)
+        {
+            self.format_check = enabled;
+        }
+        if let Some(follow) = value
+            .pointer("/files/followSymlinks")
+            .and_then(Value::as_bool)
+        {
+            self.follow_symlinks = follow;
+        }
+        if let Some(enabled) = value
+            .pointer("/files/indexLibrary")
+            .and_then(Value::as_bool)
+        {
+            self.index_library = enabled;
+        }
+        if let Some(enabled) = value.pointer("/files/lazyIndex").and_then(Value::as_bool) {
+            self.lazy_index = enabled;
+        }
+        if let Some(rules) = value
+            .pointer("/diagnostics/overrides")
+            .and_then(Value::as_array)
+        {
+            self.diagnostic_overrides =
+                rules.iter().filter_map(DiagnosticOverride::parse).collect();
+        }
+    }
+}
@@ -0,0 +1,106 @@
+//! Definitions repeated across files. Repeats within a single file are caught
+//! by the semantic pass; this needs the whole workspace.
+
+use super::semantic::definition_namespace;
+use super::DiagnosticCode;
+use crate::state::File;
+use crate::symbols::analysis::{location_from_span, range_from_span};
+use std::collections::{HashMap, HashSet};
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticRelatedInformation, DiagnosticSeverity, Location, NumberOrString, Url,
+};
+
+/// Top-level definitions across the workspace, keyed by name and namespace.
+#[derive(Default)]
+pub(crate) struct DefinitionIndex {
+    definitions: HashMap<(String, u8), Vec<Location>>,
+    scattered: HashSet<String>,
+}
+
+impl DefinitionIndex {
+    pub(crate) fn new<'a, I>(files: I) -> Self
+    where
+        I: IntoIterator<Item = (&'a Url, &'a File)>,
+    {
+        let mut index = Self::default();
+        for (uri, file) in files {
+            let Some(parsed) = file.parsed() else {
+                continue;
+            };
+            for decl in &parsed.decls {
+                if decl.scope != sail_parser::Scope::TopLevel {
+                    continue;
+                }
+                if decl.is_scattered {
+                    index.scattered.insert(decl.name.clone());
+                }
+                if decl.role != sail_parser::DeclRole::Definition {
+                    continue;
+                }
+                let Some(namespace) = definition_namespace(decl.kind) else {
+                    continue;
+                };
+                index
+                    .definitions
+                    .entry((decl.name.clone(), namespace))
+                    .or_default()
+                    .push(location_from_span(uri, file, decl.span));
+            }
+        }
+        index
+    }
+
+    /// Warn at each definition in `file` whose name is also defined in another
+    /// file, pointing at the other definitions.
+    pub(crate) fn duplicate_diagnostics(&self, uri: &Url, file: &File) -> Vec<Diagnostic> {
+        let Some(parsed) = file.parsed() else {
+            return Vec::new();
+        };
+        let mut diagnostics = Vec::new();
+        for decl in &parsed.decls {
+            if decl.scope != sail_parser::Scope::TopLevel
+                || decl.role != sail_parser::DeclRole::Definition
+                || self.scattered.contains(&decl.name)
+            {
+                continue;
+            }
+            let Some(namespace) = definition_namespace(decl.kind) else {
+                continue;
+            };
+            let Some(locations) = self.definitions.get(&(decl.name.clone(), namespace)) else {
+                continue;
+            };
+            let others: Vec<&Location> = locations.iter().filter(|loc| loc.uri != *uri).collect();
+            if others.is_empty() {
+                continue;
+            }
+
+            let mut files: Vec<&str> = others
+                .iter()
+                .filter_map(|loc| loc.uri.path_segments()?.next_back())
+                .collect();
+            files.sort_unstable();
+            files.dedup();
+            diagnostics.push(Diagnostic {
+                range: range_from_span(file, decl.span),
+                severity: Some(DiagnosticSeverity::WARNING),
+                code: Some(NumberOrString::String(
+                    DiagnosticCode::DuplicateDefinition.as_str().to_string(),
+                )),
+                source: Some("Sail".to_string()),
+                message: format!("`{}` is also defined in {}", decl.name, files.join(", ")),
+                related_information: Some(
+                    others
+                        .into_iter()
+                        .map(|location| DiagnosticRelatedInformation {
+                            location: location.clone(),
+                            message: "other definition".to_string(),
+                        })
+                        .collect(),
+                ),
+                ..Default::default()
+            });
+        }
+        diagnostics
+    }
+}
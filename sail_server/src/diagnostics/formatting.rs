@@ -0,0 +1,44 @@
+//! Opt-in check (`diagnostics.formatting`) that a document is laid out the
+//! way the formatter would lay it out.
+
+use super::{Diagnostic, DiagnosticCode, Severity};
+use crate::formatting::format_document_text;
+use crate::state::File;
+use tower_lsp::lsp_types::{Diagnostic as LspDiagnostic, FormattingOptions, Position, Range};
+
+/// Note on the first line formatting with `options` would change, if any.
+/// Files with syntax errors are not checked, as formatting does not apply to
+/// them.
+pub(crate) fn unformatted_diagnostic(
+    file: &File,
+    options: &FormattingOptions,
+) -> Option<LspDiagnostic> {
+    if file.has_syntax_errors() {
+        return None;
+    }
+    let original = file.source.text();
+    let formatted = format_document_text(original, options);
+    if formatted == original {
+        return None;
+    }
+    let mut original_lines = original.lines();
+    let mut formatted_lines = formatted.lines();
+    let mut line = 0u32;
+    let text = loop {
+        match (original_lines.next(), formatted_lines.next()) {
+            (Some(a), Some(b)) if a == b => line += 1,
+            (Some(a), _) => break a,
+            (None, _) => break "",
+        }
+    };
+    let end = text.encode_utf16().count() as u32;
+    Some(
+        Diagnostic::new(
+            DiagnosticCode::Unformatted,
+            "file is not formatted".to_string(),
+            Range::new(Position::new(line, 0), Position::new(line, end)),
+            Severity::Information,
+        )
+        .to_proto(),
+    )
+}
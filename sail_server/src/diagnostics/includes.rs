@@ -0,0 +1,56 @@
+//! `$include` directives whose target is missing. Files cache the result
+//! until they change or watched files are created or deleted, so the warning
+//! goes away once the file exists.
+
+use super::{Diagnostic, DiagnosticCode, Severity};
+use crate::formatting::include_link_target;
+use crate::state::File;
+use std::path::Path;
+use tower_lsp::lsp_types::{Diagnostic as LspDiagnostic, Range, Url};
+
+/// Warn on each `$include "..."` (relative to the file) or `$include <...>`
+/// (relative to `library_dir`) that names a file that does not exist.
+/// Library includes are not checked when the library cannot be found.
+pub(crate) fn unresolved_include_diagnostics(
+    uri: &Url,
+    file: &File,
+    library_dir: Option<&Path>,
+) -> Vec<LspDiagnostic> {
+    let Some(tokens) = file.tokens.as_deref() else {
+        return Vec::new();
+    };
+    let mut diagnostics = Vec::new();
+    for (token, span) in tokens {
+        let sail_parser::Token::Directive {
+            name,
+            payload: Some(payload),
+        } = token
+        else {
+            continue;
+        };
+        if name != "include" {
+            continue;
+        }
+        let Some((target, range)) = include_link_target(uri, payload, library_dir) else {
+            continue;
+        };
+        if target.to_file_path().is_ok_and(|path| path.is_file()) {
+            continue;
+        }
+        let payload_start = span.start + 1 + name.len();
+        let quoted = &payload[range.clone()];
+        diagnostics.push(
+            Diagnostic::new(
+                DiagnosticCode::UnresolvedInclude,
+                format!("Included file {quoted} not found"),
+                Range::new(
+                    file.source.position_at(payload_start + range.start),
+                    file.source.position_at(payload_start + range.end),
+                ),
+                Severity::Warning,
+            )
+            .to_proto(),
+        );
+    }
+    diagnostics
+}
@@ -1,17 +1,24 @@
+pub(crate) mod duplicates;
+pub(crate) mod formatting;
+pub(crate) mod includes;
 pub mod parse;
 pub(crate) mod reporting;
 pub mod semantic;
 pub(crate) mod type_error;
 
+pub(crate) use duplicates::DefinitionIndex;
+pub(crate) use formatting::unformatted_diagnostic;
+pub(crate) use includes::unresolved_include_diagnostics;
 pub(crate) use parse::compute_parse_diagnostics;
 pub(crate) use semantic::compute_semantic_diagnostics;
 
+use crate::config::DiagnosticOverride;
 use crate::state::File;
 use std::collections::{hash_map::DefaultHasher, HashMap};
 use std::hash::{Hash, Hasher};
 use tower_lsp::lsp_types::{
     Diagnostic as LspDiagnostic, DiagnosticSeverity, DocumentDiagnosticReport,
-    DocumentDiagnosticReportResult, FullDocumentDiagnosticReport, Range,
+    DocumentDiagnosticReportResult, FormattingOptions, FullDocumentDiagnosticReport, Range,
     RelatedFullDocumentDiagnosticReport, RelatedUnchangedDocumentDiagnosticReport,
     UnchangedDocumentDiagnosticReport, Url, WorkspaceDiagnosticReport,
     WorkspaceDiagnosticReportResult, WorkspaceDocumentDiagnosticReport,
@@ -62,6 +69,8 @@ pub enum DiagnosticCode {
     UnreachableAfterEscape,
     PrivateAccess,
     UnsupportedRegisterType,
+    UnresolvedInclude,
+    Unformatted,
 }
 
 impl DiagnosticCode {
@@ -88,6 +97,8 @@ impl DiagnosticCode {
             DiagnosticCode::UnreachableAfterEscape => "unreachable-after-escape",
             DiagnosticCode::PrivateAccess => "private-access",
             DiagnosticCode::UnsupportedRegisterType => "unsupported-register-type",
+            DiagnosticCode::UnresolvedInclude => "unresolved-include",
+            DiagnosticCode::Unformatted => "unformatted",
         }
     }
 }
@@ -132,12 +143,55 @@ impl Diagnostic {
     }
 }
 
-fn file_diagnostic_result_id(file: &File) -> String {
+/// A file's own diagnostics, plus missing `$include` targets and definitions
+/// it repeats from other files when a workspace `index` is given, and a note
+/// when formatting with `format_check`'s options would change it, after the
+/// user's severity `overrides`.
+pub(crate) fn diagnostics_for_file(
+    uri: &Url,
+    file: &File,
+    index: Option<&DefinitionIndex>,
+    overrides: &[DiagnosticOverride],
+    format_check: Option<&FormattingOptions>,
+) -> Vec<LspDiagnostic> {
+    let mut diagnostics = file.lsp_diagnostics();
+    diagnostics.extend(file.include_diagnostics(uri, crate::state::sail_library_dir()));
+    if let Some(index) = index {
+        diagnostics.extend(index.duplicate_diagnostics(uri, file));
+    }
+    if let Some(options) = format_check {
+        diagnostics.extend(unformatted_diagnostic(file, options));
+    }
+    apply_overrides(diagnostics, overrides)
+}
+
+/// Re-grade or drop diagnostics by the first rule matching their message.
+pub(crate) fn apply_overrides(
+    diagnostics: Vec<LspDiagnostic>,
+    overrides: &[DiagnosticOverride],
+) -> Vec<LspDiagnostic> {
+    if overrides.is_empty() {
+        return diagnostics;
+    }
+    diagnostics
+        .into_iter()
+        .filter_map(|mut diagnostic| {
+            if let Some(rule) = overrides
+                .iter()
+                .find(|rule| rule.matches(&diagnostic.message))
+            {
+                diagnostic.severity = Some(rule.severity?);
+            }
+            Some(diagnostic)
+        })
+        .collect()
+}
+
+fn file_diagnostic_result_id(file: &File, lsp_diags: &[LspDiagnostic]) -> String {
     let mut hasher = DefaultHasher::new();
-    let lsp_diags = file.lsp_diagnostics();
     file.source.text().len().hash(&mut hasher);
     lsp_diags.len().hash(&mut hasher);
-    for diagnostic in &lsp_diags {
+    for diagnostic in lsp_diags {
         diagnostic.range.start.line.hash(&mut hasher);
         diagnostic.range.start.character.hash(&mut hasher);
         diagnostic.range.end.line.hash(&mut hasher);
@@ -150,11 +204,23 @@ fn file_diagnostic_result_id(file: &File) -> String {
     format!("{:x}", hasher.finish())
 }
 
+/// Pull report for one file. Files outside the `diagnostics.scope` setting
+/// (`in_scope` false) get an empty report, clearing anything shown before.
 pub(crate) fn document_diagnostic_report_for_file(
+    uri: &Url,
     file: &File,
+    index: Option<&DefinitionIndex>,
+    overrides: &[DiagnosticOverride],
+    format_check: Option<&FormattingOptions>,
     previous_result_id: Option<&str>,
+    in_scope: bool,
 ) -> DocumentDiagnosticReportResult {
-    let result_id = file_diagnostic_result_id(file);
+    let items = if in_scope {
+        diagnostics_for_file(uri, file, index, overrides, format_check)
+    } else {
+        Vec::new()
+    };
+    let result_id = file_diagnostic_result_id(file, &items);
     if previous_result_id == Some(result_id.as_str()) {
         return DocumentDiagnosticReport::Unchanged(RelatedUnchangedDocumentDiagnosticReport {
             related_documents: None,
@@ -167,23 +233,35 @@ pub(crate) fn document_diagnostic_report_for_file(
         related_documents: None,
         full_document_diagnostic_report: FullDocumentDiagnosticReport {
             result_id: Some(result_id),
-            items: file.lsp_diagnostics(),
+            items,
         },
     })
     .into()
 }
 
+/// Pull report for every file. Files for which `in_scope` is false are still
+/// listed, with no diagnostics, so a narrowed scope clears what was shown.
+/// The formatting check is left to document reports, which cover the files
+/// being edited.
 pub(crate) fn workspace_diagnostic_report<'a, I>(
     files: I,
+    index: Option<&DefinitionIndex>,
+    overrides: &[DiagnosticOverride],
     versions: &HashMap<Url, i32>,
     previous_result_ids: &HashMap<Url, String>,
+    in_scope: impl Fn(&Url) -> bool,
 ) -> WorkspaceDiagnosticReportResult
 where
     I: IntoIterator<Item = (&'a Url, &'a File)>,
 {
     let mut items = Vec::new();
     for (uri, file) in files {
-        let result_id = file_diagnostic_result_id(file);
+        let diagnostics = if in_scope(uri) {
+            diagnostics_for_file(uri, file, index, overrides, None)
+        } else {
+            Vec::new()
+        };
+        let result_id = file_diagnostic_result_id(file, &diagnostics);
         let version = versions.get(uri).copied().map(i64::from);
         if previous_result_ids.get(uri).map(String::as_str) == Some(result_id.as_str()) {
             items.push(WorkspaceDocumentDiagnosticReport::Unchanged(
@@ -202,7 +280,7 @@ where
                     version,
                     full_document_diagnostic_report: FullDocumentDiagnosticReport {
                         result_id: Some(result_id),
-                        items: file.lsp_diagnostics(),
+                        items: diagnostics,
                     },
                 },
             ));
@@ -859,6 +859,25 @@ where
     )
 }
 
+/// Namespace a top-level definition lives in, for duplicate detection. `None`
+/// for overloads, which are designed to be extended across declarations.
+///
+/// Sail allows the same name across different namespaces:
+/// - types vs values (e.g. `type flen` and `let flen`)
+/// - val spec vs function def (e.g. `val f : T` then `function f(...)`)
+pub(crate) fn definition_namespace(kind: sail_parser::DeclKind) -> Option<u8> {
+    match kind {
+        sail_parser::DeclKind::Overload => None,
+        sail_parser::DeclKind::Type
+        | sail_parser::DeclKind::Enum
+        | sail_parser::DeclKind::Union
+        | sail_parser::DeclKind::Bitfield
+        | sail_parser::DeclKind::Newtype => Some(0), // type namespace
+        sail_parser::DeclKind::Value => Some(2), // val specs (paired with function defs)
+        _ => Some(1),                            // value namespace
+    }
+}
+
 pub(crate) fn compute_semantic_diagnostics(file: &File) -> Vec<Diagnostic> {
     compute_semantic_diagnostics_impl(
         file,
@@ -898,23 +917,8 @@ fn compute_semantic_diagnostics_impl(
         {
             continue;
         }
-        // Overloads are designed to be extended across multiple declarations.
-        if decl.kind == sail_parser::DeclKind::Overload {
+        let Some(kind_group) = definition_namespace(decl.kind) else {
             continue;
-        }
-
-        // Sail allows the same name across different namespaces:
-        // - types vs values (e.g. `type flen` and `let flen`)
-        // - val spec vs function def (e.g. `val f : T` then `function f(...)`)
-        // - scattered definitions (handled above via is_scattered)
-        let kind_group = match decl.kind {
-            sail_parser::DeclKind::Type
-            | sail_parser::DeclKind::Enum
-            | sail_parser::DeclKind::Union
-            | sail_parser::DeclKind::Bitfield
-            | sail_parser::DeclKind::Newtype => 0, // type namespace
-            sail_parser::DeclKind::Value => 2,     // val specs (paired with function defs)
-            _ => 1,                                // value namespace
         };
 
         if let Some(prev_span) = seen_defs.get(&(decl.name.clone(), kind_group)) {
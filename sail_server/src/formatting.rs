@@ -1,5 +1,6 @@
-use crate::state::{File, TextDocument};
+use crate::state::{normalize_path, File, TextDocument};
 use crate::symbols::{token_is_close_bracket, token_is_open_bracket, token_symbol_key};
+use std::collections::HashMap;
 use std::path::Path;
 use tower_lsp::lsp_types::{
     DocumentLink, FormattingOptions, LinkedEditingRanges, Range, SelectionRange, TextEdit, Url,
@@ -171,6 +172,29 @@ pub(crate) fn format_document_edits(
     }])
 }
 
+/// Formatting edits for each of `files` that needs any. Files with syntax
+/// errors are left alone, since their indentation would follow the broken
+/// bracket structure, and are returned separately.
+pub(crate) fn format_workspace_edits<'a, I>(
+    files: I,
+    options: &FormattingOptions,
+) -> (HashMap<Url, Vec<TextEdit>>, Vec<Url>)
+where
+    I: IntoIterator<Item = (&'a Url, &'a File)>,
+{
+    let mut changes = HashMap::new();
+    let mut skipped = Vec::new();
+    for (uri, file) in files {
+        if file.has_syntax_errors() {
+            skipped.push(uri.clone());
+        } else if let Some(edits) = format_document_edits(file, options) {
+            changes.insert(uri.clone(), edits);
+        }
+    }
+    skipped.sort();
+    (changes, skipped)
+}
+
 pub(crate) fn range_format_document_edits(
     file: &File,
     range: Range,
@@ -274,11 +298,64 @@ fn path_like_link_target(base_uri: &Url, text: &str) -> Option<Url> {
     Url::from_file_path(target_path).ok()
 }
 
+/// Resolve an `$include` payload to the file it names, returning the target and
+/// the byte range of the path (with its delimiters) within `payload`.
+/// `"file.sail"` is relative to the including file, `<file.sail>` to the Sail
+/// library directory.
+pub(crate) fn include_link_target(
+    base_uri: &Url,
+    payload: &str,
+    library_dir: Option<&Path>,
+) -> Option<(Url, std::ops::Range<usize>)> {
+    let start = payload.len() - payload.trim_start().len();
+    let rest = &payload[start..];
+    let (close, root) = match rest.chars().next()? {
+        '"' => ('"', base_uri.to_file_path().ok()?.parent()?.to_path_buf()),
+        '<' => ('>', library_dir?.to_path_buf()),
+        _ => return None,
+    };
+    let len = rest[1..].find(close)?;
+    let name = &rest[1..1 + len];
+    let path = Path::new(name);
+    let target = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        root.join(path)
+    };
+    let url = Url::from_file_path(normalize_path(&target)).ok()?;
+    Some((url, start..start + len + 2))
+}
+
 pub(crate) fn document_links_for_file(uri: &Url, file: &File) -> Vec<DocumentLink> {
     let mut links = Vec::new();
 
     if let Some(tokens) = file.tokens.as_deref() {
         for (token, span) in tokens {
+            if let sail_parser::Token::Directive {
+                name,
+                payload: Some(payload),
+            } = token
+            {
+                if name != "include" {
+                    continue;
+                }
+                let Some((target, range)) =
+                    include_link_target(uri, payload, crate::state::sail_library_dir())
+                else {
+                    continue;
+                };
+                let payload_start = span.start + 1 + name.len();
+                links.push(DocumentLink {
+                    range: Range::new(
+                        file.source.position_at(payload_start + range.start),
+                        file.source.position_at(payload_start + range.end),
+                    ),
+                    target: Some(target.clone()),
+                    tooltip: Some("Open included file".to_string()),
+                    data: Some(serde_json::json!({ "target": target.as_str() })),
+                });
+                continue;
+            }
             let sail_parser::Token::String(content) = token else {
                 continue;
             };
@@ -367,6 +444,17 @@ pub(crate) fn make_selection_range(
         }
     }
 
+    if let Some(tokens) = file.tokens.as_deref() {
+        for (open, close) in conditional_directive_blocks(tokens) {
+            if open.start <= offset && offset <= close.end {
+                ranges.push(Range::new(
+                    file.source.position_at(open.start),
+                    file.source.position_at(close.end),
+                ));
+            }
+        }
+    }
+
     // AST-aware selection ranges: expressions, definitions, function bodies
     if let Some(core_ast) = file.core_ast.as_deref() {
         collect_ast_ranges(core_ast, offset, file, &mut ranges);
@@ -606,7 +694,22 @@ pub(crate) fn matching_brace_position(
         .enumerate()
         .find(|(_, (_, span))| span.start <= offset && offset < span.end)?;
 
-    let (token, _span) = &tokens[idx];
+    let (token, span) = &tokens[idx];
+
+    if let sail_parser::Token::Directive { .. } = token {
+        return conditional_directive_blocks(tokens)
+            .into_iter()
+            .find_map(|(open, close)| {
+                if open == *span {
+                    Some(close)
+                } else if close == *span {
+                    Some(open)
+                } else {
+                    None
+                }
+            })
+            .map(|target| file.source.position_at(target.start));
+    }
 
     // Define bracket pairs
     let pairs: &[(sail_parser::Token, sail_parser::Token)] = &[
@@ -669,6 +772,47 @@ pub(crate) fn matching_brace_position(
     None
 }
 
+/// Pair each `$ifdef`/`$ifndef`/`$iftarget` with its `$endif`, returning the
+/// spans of both directives. Unterminated blocks are left out.
+pub(crate) fn conditional_directive_blocks(
+    tokens: &[(sail_parser::Token, sail_parser::Span)],
+) -> Vec<(sail_parser::Span, sail_parser::Span)> {
+    let mut stack = Vec::new();
+    let mut blocks = Vec::new();
+    for (token, span) in tokens {
+        let sail_parser::Token::Directive { name, .. } = token else {
+            continue;
+        };
+        match name.as_str() {
+            "ifdef" | "ifndef" | "iftarget" => stack.push(*span),
+            "endif" => {
+                if let Some(open) = stack.pop() {
+                    blocks.push((open, *span));
+                }
+            }
+            _ => {}
+        }
+    }
+    blocks.sort_by_key(|(open, _)| open.start);
+    blocks
+}
+
+/// The command-line flags set by each `$option` directive, in order.
+pub(crate) fn option_directive_flags(
+    tokens: &[(sail_parser::Token, sail_parser::Span)],
+) -> Vec<String> {
+    tokens
+        .iter()
+        .filter_map(|(token, _)| match token {
+            sail_parser::Token::Directive {
+                name,
+                payload: Some(payload),
+            } if name == "option" && !payload.trim().is_empty() => Some(payload.trim().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
 // ---------------------------------------------------------------------------
 // Move Item Up/Down
 // ---------------------------------------------------------------------------
@@ -21,7 +21,7 @@ use tower_lsp::lsp_types::{
     DocumentOnTypeFormattingParams, DocumentRangeFormattingParams, DocumentSymbolParams,
     DocumentSymbolResponse, ExecuteCommandParams, FileOperationFilter, FileOperationPattern,
     FileOperationPatternKind, FileOperationRegistrationOptions, FileSystemWatcher, FoldingRange,
-    FoldingRangeKind, FoldingRangeParams, GlobPattern, GotoDefinitionParams,
+    FoldingRangeKind, FoldingRangeParams, FormattingOptions, GlobPattern, GotoDefinitionParams,
     GotoDefinitionResponse, Hover, HoverParams, HoverProviderCapability,
     ImplementationProviderCapability, InitializeParams, InitializeResult, InitializedParams,
     InlayHint, InlayHintOptions, InlayHintParams, InlayHintServerCapabilities,
@@ -44,41 +44,45 @@ use tower_lsp::LanguageServer;
 use crate::actions::{
     add_missing_match_arms_edits, apply_demorgan_edits, block_to_line_comment_edits,
     code_action_kind_allowed, default_code_action_format_options, extract_function_edits,
-    extract_local_let_edits, flip_binexpr_edits, generate_doc_template_edits,
+    extract_local_let_edits, flip_binexpr_edits, format_document_fix, generate_doc_template_edits,
     guarded_return_edits, inline_variable_edits, invert_if_edits, lazy_code_action_data,
     line_to_block_comment_edits, organize_imports_edits, pull_assignment_up_edits,
     quick_fix_for_diagnostic, remove_unused_imports_edits, resolve_code_action_edit_from_data,
     sail_source_fix_all_kind, sort_items_edits, toggle_doc_comment_edits, unused_variable_fix,
     unwrap_block_edits, var_to_let_fix, bitfield_accessor_edits,
 };
-use crate::backend::{should_schedule_typecheck, Backend, SAIL_BUILTINS, SAIL_KEYWORDS};
+use crate::backend::{should_schedule_typecheck, Backend, RefreshSupport, SAIL_KEYWORDS};
 use crate::completion::{
-    build_completion_items, completion_prefix, completion_trigger_characters,
-    postfix_completions, pragma_completions, resolve_completion_item, snippet_completions,
+    add_completion_label_details, build_completion_items, completion_prefix,
+    completion_trigger_characters, in_numeric_literal, include_path_completions,
+    is_include_path_trigger, match_arm_completions, plain_call_completions, postfix_completions,
+    pragma_completions, resolve_completion_item, snippet_completions,
 };
 use crate::diagnostics::{document_diagnostic_report_for_file, workspace_diagnostic_report};
 use crate::formatting::{
-    document_links_for_file, format_document_edits, join_lines_edits,
-    linked_editing_ranges_for_position, make_selection_range, matching_brace_position,
-    move_item_edits, on_enter_edits, range_format_document_edits, MoveDirection,
+    conditional_directive_blocks, document_links_for_file, format_document_edits,
+    format_workspace_edits, join_lines_edits, linked_editing_ranges_for_position,
+    make_selection_range, matching_brace_position, move_item_edits, on_enter_edits,
+    option_directive_flags, range_format_document_edits, MoveDirection,
 };
-use crate::hover::hover_for_symbol;
+use crate::hover::{hover_for_operator, hover_for_symbol, hover_for_type_application};
 use crate::inlay_hints::{inlay_hints_for_range, resolve_inlay_hint};
 use crate::semantic_tokens::{
     compute_semantic_tokens, compute_semantic_tokens_delta, compute_semantic_tokens_range,
     semantic_tokens_options,
 };
-use crate::state::File;
+use crate::state::{read_file, sail_library_dir, File};
 use crate::symbols::{
     call_edges_from, call_edges_to, call_hierarchy_item, code_lens_title, code_lenses_for_file,
-    collect_implementation_counts, collect_reference_counts, extract_symbol_decls,
-    find_call_at_position, find_callable_signature, implementation_locations,
-    normalize_validated_rename, parse_named_type, reference_locations, rename_edits,
-    resolve_symbol_at, resolve_workspace_symbol, signature_help_for_position,
-    symbol_declaration_locations, symbol_definition_locations, symbol_spans_for_file,
-    token_is_close_bracket, token_is_open_bracket, token_symbol_key, type_definition_locations,
-    type_hierarchy_item, type_name_candidates_at_position, type_subtypes, type_supertypes,
-    typed_bindings, will_rename_file_edits,
+    extract_symbol_decls, field_access_definitions, find_call_at_position, find_callable_signature,
+    implementation_locations, location_from_span, member_definition_locations,
+    normalize_validated_rename, operator_decls_at, parse_named_type, reference_locations,
+    rename_edits, rename_workspace_edit, resolve_symbol_at, resolve_workspace_symbol,
+    signature_help_for_position, symbol_declaration_locations, symbol_definition_locations,
+    symbol_spans_for_file, token_is_close_bracket, token_is_open_bracket, token_symbol_key,
+    type_definition_locations, type_hierarchy_item, type_name_candidates_at_position,
+    type_subtypes, type_supertypes, type_var_definition_location, typed_bindings,
+    will_rename_file_edits,
 };
 
 /// Fuzzy match: each query character must appear in name (in order).
@@ -131,6 +135,11 @@ impl LanguageServer for Backend {
             .log_message(MessageType::INFO, "server initialized")
             .await;
 
+        // Finding the library may run `sail --dir`; later lookups are cached.
+        let library_dir = tokio::task::spawn_blocking(sail_library_dir)
+            .await
+            .ok()
+            .flatten();
         {
             let mut state = self.state.write().await;
             if let Some(workspace_folders) = params.workspace_folders {
@@ -138,6 +147,45 @@ impl LanguageServer for Backend {
                     state.disk_files.add_folder(folder.uri);
                 }
             }
+            // Older clients only send the (deprecated) root URI.
+            #[allow(deprecated)]
+            if state.disk_files.folders().is_empty() {
+                if let Some(root_uri) = params.root_uri {
+                    state.disk_files.add_folder(root_uri);
+                }
+            }
+            // Index the Sail library so symbols from `$include <...>` files
+            // resolve.
+            if let Some(folder) = library_dir.and_then(|dir| Url::from_directory_path(dir).ok()) {
+                state.disk_files.set_library_folder(folder);
+            }
+            if let Some(options) = &params.initialization_options {
+                state.config.update(options);
+            }
+            state.snippet_support = params
+                .capabilities
+                .text_document
+                .as_ref()
+                .and_then(|text_document| text_document.completion.as_ref())
+                .and_then(|completion| completion.completion_item.as_ref())
+                .and_then(|item| item.snippet_support)
+                .unwrap_or(false);
+            state.label_details_support = params
+                .capabilities
+                .text_document
+                .as_ref()
+                .and_then(|text_document| text_document.completion.as_ref())
+                .and_then(|completion| completion.completion_item.as_ref())
+                .and_then(|item| item.label_details_support)
+                .unwrap_or(false);
+            state.document_changes_support = params
+                .capabilities
+                .workspace
+                .as_ref()
+                .and_then(|workspace| workspace.workspace_edit.as_ref())
+                .and_then(|workspace_edit| workspace_edit.document_changes)
+                .unwrap_or(false);
+            state.refresh_support = RefreshSupport::from_capabilities(&params.capabilities);
         }
         self.schedule_workspace_scan().await;
 
@@ -235,6 +283,7 @@ impl LanguageServer for Backend {
                         "sail.joinLines".to_string(),
                         "sail.moveItemUp".to_string(),
                         "sail.moveItemDown".to_string(),
+                        "sail.formatWorkspace".to_string(),
                     ],
                     ..Default::default()
                 }),
@@ -312,10 +361,18 @@ impl LanguageServer for Backend {
                     method: "workspace/didChangeWatchedFiles".to_string(),
                     register_options: Some(
                         serde_json::to_value(DidChangeWatchedFilesRegistrationOptions {
-                            watchers: vec![FileSystemWatcher {
-                                glob_pattern: GlobPattern::String("**/*.sail".to_string()),
-                                kind: Some(WatchKind::all()),
-                            }],
+                            watchers: vec![
+                                FileSystemWatcher {
+                                    glob_pattern: GlobPattern::String("**/*.sail".to_string()),
+                                    kind: Some(WatchKind::all()),
+                                },
+                                FileSystemWatcher {
+                                    glob_pattern: GlobPattern::String(
+                                        "**/*.sail_project".to_string(),
+                                    ),
+                                    kind: Some(WatchKind::all()),
+                                },
+                            ],
                         })
                         .unwrap(),
                     ),
@@ -378,10 +435,36 @@ impl LanguageServer for Backend {
         self.schedule_workspace_scan().await;
     }
 
-    async fn did_change_configuration(&self, _params: DidChangeConfigurationParams) {
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
         self.client
             .log_message(MessageType::INFO, "configuration changed")
             .await;
+
+        let mut state = self.state.write().await;
+        let previous = state.config.clone();
+        state.config.update(&params.settings);
+        let config = &state.config;
+        let rescan = config.follow_symlinks != previous.follow_symlinks
+            || config.index_library != previous.index_library
+            || config.lazy_index != previous.lazy_index;
+        if config.duplicate_definitions == previous.duplicate_definitions
+            && config.diagnostics_scope == previous.diagnostics_scope
+            && config.diagnostic_overrides == previous.diagnostic_overrides
+            && config.format_check == previous.format_check
+        {
+            drop(state);
+            if rescan {
+                self.schedule_workspace_scan().await;
+            }
+            return;
+        }
+
+        // Diagnostics settings changed.
+        drop(state);
+        self.republish_diagnostics().await;
+        if rescan {
+            self.schedule_workspace_scan().await;
+        }
     }
 
     async fn did_change_watched_files(&self, params: DidChangeWatchedFilesParams) {
@@ -397,7 +480,23 @@ impl LanguageServer for Backend {
             .await;
 
         let mut state = self.state.write().await;
+        let mut rescan = false;
+        let mut created_or_deleted = false;
         for change in &params.changes {
+            created_or_deleted |= matches!(
+                change.typ,
+                tower_lsp::lsp_types::FileChangeType::CREATED
+                    | tower_lsp::lsp_types::FileChangeType::DELETED
+            );
+            // The project file decides which files are indexed, so let a full
+            // scan work out what was added or dropped.
+            let is_project_file = change.uri.path().ends_with(".sail_project");
+            let is_new_project_member = change.typ == tower_lsp::lsp_types::FileChangeType::CREATED
+                && state.disk_files.is_under_project(&change.uri);
+            if is_project_file || is_new_project_member {
+                rescan = true;
+                continue;
+            }
             match change.typ {
                 tower_lsp::lsp_types::FileChangeType::DELETED => {
                     state.disk_files.remove_file(&change.uri);
@@ -408,6 +507,15 @@ impl LanguageServer for Backend {
                     if change.uri.scheme() == "file" {
                         if let Ok(path) = change.uri.to_file_path() {
                             if let Ok(source) = std::fs::read_to_string(path) {
+                                // Saving an unmodified buffer still reports a
+                                // change; keep the parsed file we have.
+                                let unchanged = state
+                                    .disk_files
+                                    .get_file(&change.uri)
+                                    .is_some_and(|file| file.source.text() == source);
+                                if unchanged {
+                                    continue;
+                                }
                                 let file = File::new_lazy(source);
                                 state.disk_files.add_file(change.uri.clone(), file);
                             }
@@ -417,24 +525,76 @@ impl LanguageServer for Backend {
                 _ => {}
             }
         }
+        if created_or_deleted {
+            state.forget_include_checks();
+        }
+        drop(state);
+
+        // Open documents may have been warned about definitions in a deleted
+        // file, or about includes of a file that now exists or is gone.
+        if created_or_deleted {
+            self.republish_diagnostics().await;
+        }
+        if rescan {
+            self.schedule_workspace_scan().await;
+        }
     }
 
+    // Document notifications take the state lock before anything else that
+    // awaits. tower-lsp runs handlers concurrently, and the lock queues
+    // writers in arrival order, so this keeps open/change/close for a
+    // document applying in the order the client sent them.
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
-        self.client
-            .log_message(
-                MessageType::INFO,
-                format!("file opened: {}", params.text_document.uri),
-            )
-            .await;
-
         let uri = params.text_document.uri;
         let version = params.text_document.version;
         let file = File::new_lazy(params.text_document.text);
         let typecheck_file = file.clone();
-        {
+        let options = file
+            .tokens
+            .as_deref()
+            .map(|tokens| option_directive_flags(tokens))
+            .unwrap_or_default();
+        let (implicit_root, new_lazy_root) = {
             let mut state = self.state.write().await;
             state.diagnostic_versions.insert(uri.clone(), version);
             state.open_files.insert(uri.clone(), file);
+            state.out_of_sync.remove(&uri);
+            // Without a workspace folder, index the file's project, or just
+            // its neighbours, so cross-file features still see them.
+            let implicit_root = if state.disk_files.folders().is_empty() {
+                state.disk_files.add_implicit_folder(&uri)
+            } else {
+                None
+            };
+            // Recorded even when lazy indexing is off, so turning it on
+            // later still indexes the projects already open.
+            let new_root = state.disk_files.add_opened_root(&uri);
+            (implicit_root, new_root && state.config.lazy_index)
+        };
+        self.client
+            .log_message(MessageType::INFO, format!("file opened: {uri}"))
+            .await;
+        if !options.is_empty() {
+            self.client
+                .log_message(
+                    MessageType::INFO,
+                    format!(
+                        "{uri} sets $option {}; only the Sail compiler applies these",
+                        options.join(" ")
+                    ),
+                )
+                .await;
+        }
+        if let Some(folder) = &implicit_root {
+            self.client
+                .log_message(
+                    MessageType::INFO,
+                    format!("no workspace folder, indexing {folder}"),
+                )
+                .await;
+        }
+        if implicit_root.is_some() || new_lazy_root {
+            self.schedule_workspace_scan().await;
         }
         self.schedule_debounced_diagnostics(uri.clone(), version);
         if should_schedule_typecheck(&typecheck_file) {
@@ -443,28 +603,56 @@ impl LanguageServer for Backend {
     }
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
-        self.client
-            .log_message(
-                MessageType::INFO,
-                format!("file changed: {}", params.text_document.uri),
-            )
-            .await;
-
         let uri = &params.text_document.uri;
         let version = params.text_document.version;
 
-        let typecheck_file = {
+        let updated = {
             let mut state = self.state.write().await;
 
+            let mut changes = params.content_changes;
+            if state.out_of_sync.contains(uri) {
+                // Range edits would land on stale text; only a full-text
+                // change can bring the document back.
+                let Some(full) = changes.iter().rposition(|change| change.range.is_none()) else {
+                    return;
+                };
+                changes.drain(..full);
+            }
             let file = state
                 .open_files
                 .get_mut(uri)
                 .expect("document changed that isn't open");
-            file.update(params.content_changes);
-            let typecheck_file = file.clone();
-            state.diagnostic_versions.insert(uri.clone(), version);
-            typecheck_file
+            let updated = file.update(changes).map(|()| file.clone());
+            if updated.is_ok() {
+                state.out_of_sync.remove(uri);
+                state.diagnostic_versions.insert(uri.clone(), version);
+            } else {
+                state.out_of_sync.insert(uri.clone());
+                state.record_published(uri, &[]);
+            }
+            updated
         };
+        let typecheck_file = match updated {
+            Ok(file) => file,
+            Err(err) => {
+                // There is no way to ask for the full text, so the user has to
+                // reopen the document to get back in sync.
+                let message = format!("ignored invalid edit to {uri}: {err}; reopen the file");
+                self.client
+                    .log_message(MessageType::WARNING, message.clone())
+                    .await;
+                self.client
+                    .show_message(MessageType::WARNING, message)
+                    .await;
+                self.client
+                    .publish_diagnostics(uri.clone(), Vec::new(), None)
+                    .await;
+                return;
+            }
+        };
+        self.client
+            .log_message(MessageType::INFO, format!("file changed: {uri}"))
+            .await;
         self.schedule_debounced_diagnostics(uri.clone(), version);
         if should_schedule_typecheck(&typecheck_file) {
             self.schedule_debounced_typecheck(uri.clone(), version, typecheck_file);
@@ -481,18 +669,24 @@ impl LanguageServer for Backend {
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        let uri = &params.text_document.uri;
+        let had_diagnostics = {
+            let mut state = self.state.write().await;
+            state.open_files.remove(uri);
+            state.out_of_sync.remove(uri);
+            state.diagnostic_versions.remove(uri);
+            state.semantic_tokens_cache.remove(uri);
+            state.published_diagnostics.remove(uri)
+        };
         self.client
-            .log_message(
-                MessageType::INFO,
-                format!("file closed: {}", params.text_document.uri),
-            )
+            .log_message(MessageType::INFO, format!("file closed: {uri}"))
             .await;
-        let uri = &params.text_document.uri;
-
-        let mut state = self.state.write().await;
-        state.open_files.remove(uri);
-        state.diagnostic_versions.remove(uri);
-        state.semantic_tokens_cache.remove(uri);
+        // Only open documents are published, so clear whatever we last sent.
+        if had_diagnostics {
+            self.client
+                .publish_diagnostics(uri.clone(), Vec::new(), None)
+                .await;
+        }
     }
 
     async fn goto_definition(
@@ -511,10 +705,30 @@ impl LanguageServer for Backend {
 
         let position = params.text_document_position_params.position;
 
+        let offset = file.source.offset_at(&position);
+        if let Some((_, decls)) = operator_decls_at(state.all_files(), file, offset) {
+            let mut locations = decls
+                .iter()
+                .map(|(uri, file, decl)| location_from_span(uri, file, decl.item_span))
+                .collect::<Vec<_>>();
+            let members = decls
+                .iter()
+                .flat_map(|(_, _, decl)| decl.members.iter().cloned())
+                .collect::<Vec<_>>();
+            locations.extend(member_definition_locations(state.all_files(), &members));
+            return Ok(Some(GotoDefinitionResponse::Array(locations)));
+        }
+
         if let Some(token) = file.token_at(position) {
             if let Some(symbol_key) = token_symbol_key(&token.0) {
                 if symbol_key.starts_with('\'') {
-                    return Ok(None);
+                    return Ok(type_var_definition_location(uri, file, position)
+                        .map(GotoDefinitionResponse::Scalar));
+                }
+                if let Some(fields) = field_access_definitions(state.all_files(), file, token.1) {
+                    return Ok(
+                        (!fields.is_empty()).then_some(GotoDefinitionResponse::Array(fields))
+                    );
                 }
                 let definitions = symbol_definition_locations(state.all_files(), uri, &symbol_key);
 
@@ -543,7 +757,8 @@ impl LanguageServer for Backend {
             return Ok(None);
         };
         if symbol_key.starts_with('\'') {
-            return Ok(None);
+            return Ok(type_var_definition_location(uri, file, position)
+                .map(GotoDeclarationResponse::Scalar));
         }
 
         let declarations = symbol_declaration_locations(state.all_files(), uri, &symbol_key);
@@ -774,7 +989,7 @@ impl LanguageServer for Backend {
         let Some(symbol) = resolve_symbol_at(file, position) else {
             return Ok(None);
         };
-        let locations = reference_locations(state.all_files(), uri, &symbol, include_declaration);
+        let locations = reference_locations(state.user_files(), uri, &symbol, include_declaration);
 
         Ok(Some(locations))
     }
@@ -824,7 +1039,9 @@ impl LanguageServer for Backend {
                         tags: None,
                         deprecated: None,
                         location: Location::new(uri.clone(), range),
-                        container_name: Some(decl.detail.to_string()),
+                        container_name: Some(
+                            decl.container.unwrap_or_else(|| decl.detail.to_string()),
+                        ),
                     },
                 ));
             }
@@ -975,6 +1192,21 @@ impl LanguageServer for Backend {
             });
         }
 
+        for (open, close) in conditional_directive_blocks(tokens) {
+            let start = file.source.position_at(open.start).line;
+            let end = file.source.position_at(close.start).line;
+            if end > start {
+                ranges.push(FoldingRange {
+                    start_line: start,
+                    start_character: None,
+                    end_line: end,
+                    end_character: None,
+                    kind: Some(FoldingRangeKind::Region),
+                    collapsed_text: None,
+                });
+            }
+        }
+
         // Fold consecutive line comments (// ...) and consecutive $include directives.
         let text = file.source.text();
         let lines: Vec<&str> = text.split('\n').collect();
@@ -1205,6 +1437,24 @@ impl LanguageServer for Backend {
                     }));
                 }
             }
+            // Not part of "Fix all": the edit replaces the whole document.
+            if let Some((title, edit, is_preferred)) =
+                format_document_fix(file, diagnostic, &state.format_options())
+            {
+                let kind = CodeActionKind::QUICKFIX;
+                if code_action_kind_allowed(&requested_kinds, &kind) {
+                    actions.push(CodeActionOrCommand::CodeAction(CodeAction {
+                        title,
+                        kind: Some(kind),
+                        diagnostics: Some(vec![diagnostic.clone()]),
+                        edit: None,
+                        command: None,
+                        is_preferred: Some(is_preferred),
+                        disabled: None,
+                        data: Some(lazy_code_action_data(uri, &[edit])),
+                    }));
+                }
+            }
         }
 
         // "Fix all X" actions: group fixes by diagnostic code.
@@ -1595,23 +1845,53 @@ impl LanguageServer for Backend {
         let position = params.text_document_position.position;
 
         let state = self.state.read().await;
-        let Some(file) = state.get_file(uri) else {
+        // Completion can arrive before didOpen; fall back to the saved file.
+        let saved = match state.get_file(uri) {
+            Some(_) => None,
+            None => read_file(uri),
+        };
+        let Some(file) = state.get_file(uri).or(saved.as_ref()) else {
             return Ok(None);
         };
 
         let offset = file.source.offset_at(&position);
+        if in_numeric_literal(file.source.text(), offset) {
+            return Ok(None);
+        }
+        if let Some(items) = include_path_completions(uri, file, offset, sail_library_dir()) {
+            return Ok((!items.is_empty()).then_some(CompletionResponse::Array(items)));
+        }
+        let trigger = params
+            .context
+            .as_ref()
+            .and_then(|context| context.trigger_character.as_deref());
+        if trigger.is_some_and(is_include_path_trigger) {
+            return Ok(None);
+        }
         let prefix = completion_prefix(file.source.text(), offset);
-        let all_files = state.all_files().collect::<Vec<_>>();
+        let mut all_files = state.all_files().collect::<Vec<_>>();
+        all_files.extend(saved.as_ref().map(|saved| (uri, saved)));
+        let arm_items = match_arm_completions(&all_files, uri, file, offset, prefix);
+        if !arm_items.is_empty() {
+            return Ok(Some(CompletionResponse::Array(arm_items)));
+        }
+
         let mut items = build_completion_items(
             all_files.iter().copied(),
             uri,
             file.source.text(),
             offset,
-            prefix,
-            SAIL_KEYWORDS,
-            SAIL_BUILTINS,
+            prefix,
+            |uri| state.disk_files.is_library_file(uri),
         );
 
+        if !(state.snippet_support && state.config.completion_call_parentheses) {
+            plain_call_completions(&mut items);
+        }
+        if state.label_details_support {
+            add_completion_label_details(&mut items, all_files.iter().copied());
+        }
+
         // Add postfix completions (e.g. expr.if, expr.match, expr.let)
         items.extend(postfix_completions(file.source.text(), offset, prefix));
 
@@ -1665,9 +1945,16 @@ impl LanguageServer for Backend {
             .into());
         };
 
+        let format_options = state.format_options();
+        let format_check = state.config.format_check && state.open_files.contains_key(uri);
         Ok(document_diagnostic_report_for_file(
+            uri,
             file,
+            state.definition_index().as_ref(),
+            &state.config.diagnostic_overrides,
+            format_check.then_some(&format_options),
             params.previous_result_id.as_deref(),
+            state.reports_diagnostics_for(uri),
         ))
     }
 
@@ -1683,8 +1970,11 @@ impl LanguageServer for Backend {
             .collect::<HashMap<_, _>>();
         Ok(workspace_diagnostic_report(
             state.all_files(),
+            state.definition_index().as_ref(),
+            &state.config.diagnostic_overrides,
             &state.diagnostic_versions,
             &previous,
+            |uri| state.reports_diagnostics_for(uri),
         ))
     }
 
@@ -1695,6 +1985,12 @@ impl LanguageServer for Backend {
         let Some(file) = state.get_file(uri) else {
             return Ok(None);
         };
+        if let Some(hover) = hover_for_operator(state.all_files(), uri, file, position) {
+            return Ok(Some(hover));
+        }
+        if let Some(hover) = hover_for_type_application(file, position) {
+            return Ok(Some(hover));
+        }
         let Some((token, span)) = file.token_at(position) else {
             return Ok(None);
         };
@@ -1808,6 +2104,7 @@ impl LanguageServer for Backend {
     }
 
     async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        self.remember_format_options(&params.options).await;
         self.with_file(&params.text_document.uri, |_, file| {
             format_document_edits(file, &params.options)
         })
@@ -1863,6 +2160,7 @@ impl LanguageServer for Backend {
         &self,
         params: DocumentRangeFormattingParams,
     ) -> Result<Option<Vec<TextEdit>>> {
+        self.remember_format_options(&params.options).await;
         self.with_file(&params.text_document.uri, |_, file| {
             range_format_document_edits(file, params.range, &params.options)
         })
@@ -1914,10 +2212,7 @@ impl LanguageServer for Backend {
         let Some(file) = state.get_file(uri) else {
             return Ok(None);
         };
-        let all_files = state.all_files().collect::<Vec<_>>();
-        let ref_counts = collect_reference_counts(&all_files);
-        let impl_counts = collect_implementation_counts(&all_files);
-        let lenses = code_lenses_for_file(file, &ref_counts, &impl_counts);
+        let lenses = code_lenses_for_file(file);
         if lenses.is_empty() {
             return Ok(None);
         }
@@ -1927,7 +2222,9 @@ impl LanguageServer for Backend {
     async fn code_lens_resolve(&self, mut params: CodeLens) -> Result<CodeLens> {
         if params.command.is_none() {
             if let Some(data) = params.data.as_ref() {
-                if let Some(title) = code_lens_title(data) {
+                let state = self.state.read().await;
+                let all_files = state.all_files().collect::<Vec<_>>();
+                if let Some(title) = code_lens_title(data, &all_files) {
                     params.command = Some(Command {
                         title,
                         command: "sail.noop".to_string(),
@@ -1970,6 +2267,9 @@ impl LanguageServer for Backend {
                 self.handle_move_item(&params.arguments, MoveDirection::Down)
                     .await;
             }
+            "sail.formatWorkspace" => {
+                return Ok(Some(self.handle_format_workspace(&params.arguments).await));
+            }
             _ => {
                 self.client
                     .log_message(
@@ -1984,7 +2284,7 @@ impl LanguageServer for Backend {
 
     async fn will_rename_files(&self, params: RenameFilesParams) -> Result<Option<WorkspaceEdit>> {
         let state = self.state.read().await;
-        let Some(changes) = will_rename_file_edits(state.all_files(), &params) else {
+        let Some(changes) = will_rename_file_edits(state.user_files(), &params) else {
             return Ok(None);
         };
         Ok(Some(WorkspaceEdit {
@@ -2014,13 +2314,20 @@ impl LanguageServer for Backend {
         let Some(symbol) = resolve_symbol_at(file, position) else {
             return Ok(None);
         };
-        let changes = rename_edits(state.all_files(), uri, &symbol, &validated_name);
+        if state.is_library_symbol(uri, &symbol) {
+            return Err(tower_lsp::jsonrpc::Error::invalid_params(format!(
+                "`{}` is defined in the Sail library and cannot be renamed",
+                symbol.name
+            )));
+        }
+        let changes = rename_edits(state.user_files(), uri, &symbol, &validated_name);
 
-        Ok(Some(WorkspaceEdit {
-            changes: Some(changes),
-            document_changes: None,
-            change_annotations: None,
-        }))
+        Ok(Some(rename_workspace_edit(
+            changes,
+            state
+                .document_changes_support
+                .then_some(&state.diagnostic_versions),
+        )))
     }
 
     async fn prepare_rename(
@@ -2493,6 +2800,43 @@ impl Backend {
         }))
     }
 
+    async fn handle_format_workspace(&self, args: &[serde_json::Value]) -> serde_json::Value {
+        let options = args
+            .first()
+            .and_then(|value| serde_json::from_value::<FormattingOptions>(value.clone()).ok())
+            .unwrap_or_else(default_code_action_format_options);
+        let state = self.state.read().await;
+        let (changes, skipped) = format_workspace_edits(state.user_files(), &options);
+        let formatted = changes.len();
+        let edit = rename_workspace_edit(
+            changes,
+            state
+                .document_changes_support
+                .then_some(&state.diagnostic_versions),
+        );
+        drop(state);
+
+        for uri in &skipped {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("not formatting {uri}: it has syntax errors"),
+                )
+                .await;
+        }
+        if formatted > 0 {
+            if let Err(err) = self.client.apply_edit(edit).await {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("applying workspace formatting failed: {err}"),
+                    )
+                    .await;
+            }
+        }
+        serde_json::json!({ "formatted": formatted, "skipped": skipped })
+    }
+
     async fn handle_join_lines(&self, args: &[serde_json::Value]) {
         let Some((uri, range)) = Self::parse_uri_range(args) else {
             return;
@@ -3,10 +3,11 @@ pub(crate) mod support;
 use self::support::{binding_type_hint, infer_call_arg_types_at_position};
 use crate::state::File;
 use crate::symbols::{
-    builtin_docs, extract_comments,
-    find_call_at_position, find_callable_signature, instantiate_signature, token_is_close_bracket,
-    token_is_open_bracket,
+    builtin_docs, enclosing_item_name, extract_comments, find_call_at_position,
+    find_callable_signature, instantiate_signature, operator_decls_at, parse_named_type,
+    token_is_close_bracket, token_is_open_bracket,
 };
+use sail_parser::core_ast::{DefinitionKind, NamedDefDetail};
 use sail_parser::{
     find_enum_name_for_member, find_named_members, find_top_level_item_span, Decl, DeclKind,
     DeclRole, NamedDefKind, Scope, Token,
@@ -15,7 +16,7 @@ use tower_lsp::lsp_types::{
     Hover, HoverContents, MarkupContent, MarkupKind, Position, Range, SymbolKind, Url,
 };
 
-pub(crate) use support::infer_expr_type_text;
+pub(crate) use support::{expr_type_in_range, infer_expr_type_text};
 
 pub(crate) fn hover_for_symbol<'a, I>(
     files: I,
@@ -28,12 +29,18 @@ pub(crate) fn hover_for_symbol<'a, I>(
 where
     I: IntoIterator<Item = (&'a Url, &'a File)>,
 {
+    let current_offset = current_file.source.offset_at(&position);
+
     if symbol_key.starts_with('\'') {
-        return Some(markdown_hover(fenced_sail(symbol_key), hover_range));
+        let note = match enclosing_item_name(current_file, current_offset) {
+            Some(item) => format!("type variable, in scope of `{item}`"),
+            None => "type variable".to_string(),
+        };
+        let markdown = [fenced_sail(symbol_key), "___".to_string(), note];
+        return Some(markdown_hover(markdown.join("\n\n"), hover_range));
     }
 
     let files = files.into_iter().collect::<Vec<_>>();
-    let current_offset = current_file.source.offset_at(&position);
 
     // RA-style: Builtin docs
     if let Some(doc) = builtin_docs(symbol_key) {
@@ -109,8 +116,24 @@ where
             }
         }
 
-        markdown.push("___".to_string());
-        markdown.push(fenced_sail(&headline));
+        if !headline.trim().is_empty() {
+            markdown.push("___".to_string());
+            markdown.push(fenced_sail(&headline));
+        }
+
+        if decl_ref.decl.kind == DeclKind::Parameter {
+            if let Some(item) = enclosing_item_name(decl_ref.file, decl_ref.decl.span.start) {
+                markdown.push("___".to_string());
+                markdown.push(format!("parameter of `{item}`"));
+            }
+        }
+
+        if decl_ref.decl.kind == DeclKind::Register {
+            if let Some(layout) = register_bitfield_layout(&files, decl_ref.file, &decl_ref.decl) {
+                markdown.push("___".to_string());
+                markdown.push(layout);
+            }
+        }
 
         // Overload inspection
         if decl_ref.decl.kind == DeclKind::Overload {
@@ -187,6 +210,137 @@ where
     Some(markdown_hover(markup, hover_range))
 }
 
+/// Hover for a user-defined operator such as `<_u`: its fixity and the
+/// functions it is overloaded with.
+pub(crate) fn hover_for_operator<'a, I>(
+    files: I,
+    current_uri: &Url,
+    current_file: &File,
+    position: Position,
+) -> Option<Hover>
+where
+    I: IntoIterator<Item = (&'a Url, &'a File)>,
+{
+    let files = files.into_iter().collect::<Vec<_>>();
+    let offset = current_file.source.offset_at(&position);
+    let (span, decls) = operator_decls_at(files.iter().copied(), current_file, offset)?;
+    let operator = current_file.source.text().get(span.start..span.end)?;
+
+    let mut markdown = vec![format!("**operator** **{operator}**")];
+    for (_, file, decl) in decls.iter().filter(|(_, _, decl)| decl.is_fixity) {
+        if let Some(text) = file
+            .source
+            .text()
+            .get(decl.item_span.start..decl.item_span.end)
+        {
+            markdown.push("___".to_string());
+            markdown.push(fenced_sail(text.trim()));
+        }
+    }
+    let members = decls
+        .iter()
+        .flat_map(|(_, _, decl)| decl.members.iter())
+        .collect::<Vec<_>>();
+    if !members.is_empty() {
+        markdown.push("___".to_string());
+        markdown.push("**overloads:**".to_string());
+        for member in members {
+            if let Some(sig) = find_callable_signature(files.iter().copied(), current_uri, member) {
+                markdown.push(fenced_sail(&sig.label));
+            } else {
+                markdown.push(format!("- `{member}`"));
+            }
+        }
+    }
+
+    let range = Range::new(
+        current_file.source.position_at(span.start),
+        current_file.source.position_at(span.end),
+    );
+    Some(markdown_hover(markdown.join("\n\n"), range))
+}
+
+/// Builtin type constructors whose applications hover as a whole.
+const TYPE_CONSTRUCTORS: &[&str] = &[
+    "bits",
+    "bitvector",
+    "vector",
+    "range",
+    "atom",
+    "implicit",
+    "list",
+];
+
+/// Hover for a builtin type application such as `vector('n, bit)`, from its
+/// constructor or anything between its parentheses other than type
+/// variables and user-defined names, which have hovers of their own.
+pub(crate) fn hover_for_type_application(file: &File, position: Position) -> Option<Hover> {
+    let tokens = file.tokens.as_deref()?;
+    let (token, span) = file.token_at(position)?;
+    match token {
+        Token::TyVal(_) => return None,
+        Token::Id(name) if builtin_docs(name).is_none() => return None,
+        _ => {}
+    }
+    let index = tokens.iter().position(|(_, s)| s.start == span.start)?;
+
+    let is_constructor = |i: usize| match &tokens[i].0 {
+        Token::Id(name) => TYPE_CONSTRUCTORS.contains(&name.as_str()),
+        _ => false,
+    };
+    let open = if is_constructor(index)
+        && matches!(tokens.get(index + 1), Some((Token::LeftBracket, _)))
+    {
+        index + 1
+    } else {
+        // Innermost enclosing `(` that follows a type constructor.
+        let mut depth = 0usize;
+        let mut found = None;
+        for i in (0..=index).rev() {
+            match &tokens[i].0 {
+                Token::RightBracket if i != index => depth += 1,
+                Token::LeftBracket if depth > 0 => depth -= 1,
+                Token::LeftBracket if i > 0 && is_constructor(i - 1) => {
+                    found = Some(i);
+                    break;
+                }
+                Token::LeftCurlyBracket | Token::RightCurlyBracket | Token::Semicolon => break,
+                token if token_starts_declaration(token) => break,
+                _ => {}
+            }
+        }
+        found?
+    };
+
+    let mut depth = 0usize;
+    let close = (open..tokens.len()).find(|&i| {
+        match tokens[i].0 {
+            Token::LeftBracket => depth += 1,
+            Token::RightBracket => depth -= 1,
+            _ => {}
+        }
+        depth == 0
+    })?;
+    let (Token::Id(constructor), start) = &tokens[open - 1] else {
+        return None;
+    };
+    let end = tokens[close].1.end;
+    let text = file.source.text().get(start.start..end)?;
+
+    let markdown = [
+        format!("**builtin** **{constructor}**"),
+        "___".to_string(),
+        fenced_sail(text),
+        "___".to_string(),
+        builtin_docs(constructor)?.to_string(),
+    ];
+    let range = Range::new(
+        file.source.position_at(start.start),
+        file.source.position_at(end),
+    );
+    Some(markdown_hover(markdown.join("\n\n"), range))
+}
+
 fn overload_members(file: &File, decl: &Decl) -> Vec<String> {
     if let Some(ast) = file.core_ast() {
         if let Some(members) = find_named_members(ast, NamedDefKind::Overload, decl.span) {
@@ -242,8 +396,57 @@ fn markdown_hover(markdown: String, range: Range) -> Hover {
     }
 }
 
+/// Fence `text` as a Sail code block. The fence is longer than any backtick
+/// run inside `text` (e.g. in a string literal) so it cannot close early.
 fn fenced_sail(text: &str) -> String {
-    format!("```sail\n{text}\n```")
+    let longest_run = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest_run.max(2) + 1);
+    format!("{fence}sail\n{text}\n{fence}")
+}
+
+/// The fields of the bitfield type a register is declared with, one
+/// `` - `NAME` : high .. low `` line each.
+fn register_bitfield_layout(files: &[(&Url, &File)], file: &File, decl: &Decl) -> Option<String> {
+    let binding = file
+        .parsed()?
+        .typed_bindings
+        .iter()
+        .find(|binding| binding.name_span == decl.span)?;
+    let ty = file
+        .source
+        .text()
+        .get(binding.ty_span.start..binding.ty_span.end)?;
+    let type_name = parse_named_type(ty)?;
+
+    for (_, candidate) in files {
+        let Some(ast) = candidate.core_ast() else {
+            continue;
+        };
+        for (def, _) in &ast.defs {
+            let DefinitionKind::Named(named) = &def.kind else {
+                continue;
+            };
+            if named.kind != NamedDefKind::Bitfield || named.name.0 != type_name {
+                continue;
+            }
+            let Some(NamedDefDetail::Bitfield { fields }) = &named.detail else {
+                continue;
+            };
+            let text = candidate.source.text();
+            let bound = |span: &sail_parser::Span| text.get(span.start..span.end).map(str::trim);
+            let mut lines = vec![format!("**bitfield** `{type_name}`")];
+            for (field, _) in fields {
+                let high = bound(&field.high.1).unwrap_or("?");
+                let bits = match field.low.as_ref().and_then(|low| bound(&low.1)) {
+                    Some(low) => format!("{high} .. {low}"),
+                    None => high.to_string(),
+                };
+                lines.push(format!("- `{}` : {bits}", field.name.0));
+            }
+            return Some(lines.join("\n"));
+        }
+    }
+    None
 }
 
 fn decl_kind_label(kind: DeclKind) -> &'static str {
@@ -577,6 +780,78 @@ mod tests {
         assert!(markdown.contains("*in main.sail*"));
     }
 
+    #[test]
+    fn fences_code_containing_backticks() {
+        let source = "function quote() -> string = \"```\"\n".to_string();
+        let file = File::new(source.clone());
+        let uri = Url::parse("file:///tmp/main.sail").unwrap();
+        let offset = source.find("quote").unwrap();
+        let pos = file.source.position_at(offset);
+
+        let hover = hover_for_symbol(
+            std::iter::once((&uri, &file)),
+            &uri,
+            &file,
+            pos,
+            Range::new(pos, file.source.position_at(offset + "quote".len())),
+            "quote",
+        )
+        .expect("hover");
+        let markdown = hover_markdown(hover);
+        assert!(markdown.contains("````sail\nfunction quote() -> string = \"```\"\n````"));
+    }
+
+    #[test]
+    fn shows_operator_fixity_and_overloads() {
+        let source = "infix 4 <_u\nval lt_bits : (bits(8), bits(8)) -> bool\noverload operator <_u = {lt_bits}\nfunction f(a, b) = a <_u b\n".to_string();
+        let file = File::new(source.clone());
+        let uri = Url::parse("file:///tmp/main.sail").unwrap();
+        let use_offset = source.rfind("<_u").unwrap();
+
+        let hover = hover_for_operator(
+            std::iter::once((&uri, &file)),
+            &uri,
+            &file,
+            file.source.position_at(use_offset + 1),
+        )
+        .expect("hover");
+        assert_eq!(
+            hover.range,
+            Some(Range::new(
+                file.source.position_at(use_offset),
+                file.source.position_at(use_offset + 3),
+            ))
+        );
+        let markdown = hover_markdown(hover);
+        assert!(markdown.contains("**operator** **<_u**"));
+        assert!(markdown.contains("```sail\ninfix 4 <_u\n```"));
+        assert!(markdown.contains("lt_bits"));
+
+        let arrow = file.source.position_at(source.find("->").unwrap());
+        assert!(hover_for_operator(std::iter::once((&uri, &file)), &uri, &file, arrow).is_none());
+    }
+
+    #[test]
+    fn shows_whole_builtin_type_application() {
+        let source = "val f : vector('n, bit) -> bits(16)\n";
+        let file = File::new(source.to_string());
+        let at = |offset: usize| file.source.position_at(offset);
+
+        let inside = source.find("bit)").unwrap();
+        let markdown = hover_markdown(hover_for_type_application(&file, at(inside)).unwrap());
+        assert!(markdown.contains("**builtin** **vector**"));
+        assert!(markdown.contains("vector('n, bit)"));
+        assert!(markdown.contains("`'n` elements"));
+
+        let width = source.find("16").unwrap();
+        let markdown = hover_markdown(hover_for_type_application(&file, at(width)).unwrap());
+        assert!(markdown.contains("bits(16)"));
+
+        let type_var = source.find("'n").unwrap();
+        assert!(hover_for_type_application(&file, at(type_var)).is_none());
+        assert!(hover_for_type_application(&file, at(source.find('f').unwrap())).is_none());
+    }
+
     #[test]
     fn shows_local_binding_type_hint() {
         let source = "function foo() = {\n  let x : bits(32) = 1;\n  x\n}\n".to_string();
@@ -638,6 +913,82 @@ mod tests {
         let markdown = hover_markdown(hover);
         assert!(markdown.contains("**parameter** **x**"));
         assert!(markdown.contains("```sail\nparameter x : bits(32)\n```"));
+        assert!(markdown.contains("parameter of `foo`"));
+    }
+
+    #[test]
+    fn shows_untyped_parameter_type_from_val_spec() {
+        let source = "val foo : (bits(8), int) -> unit\nfunction foo(x, y) = y\n".to_string();
+        let file = File::new(source.clone());
+        let uri = Url::parse("file:///tmp/main.sail").unwrap();
+        let offset = source.rfind('y').unwrap();
+        let pos = file.source.position_at(offset);
+
+        let hover = hover_for_symbol(
+            std::iter::once((&uri, &file)),
+            &uri,
+            &file,
+            pos,
+            Range::new(pos, file.source.position_at(offset + 1)),
+            "y",
+        )
+        .expect("hover");
+        let markdown = hover_markdown(hover);
+        assert!(markdown.contains("**parameter** **y**"));
+        assert!(markdown.contains("```sail\nparameter y : int\n```"));
+        assert!(markdown.contains("parameter of `foo`"));
+    }
+
+    #[test]
+    fn types_a_selected_expression() {
+        let source =
+            "val widen : bits(8) -> bits(16)\nfunction f(x : bits(8)) -> bits(16) = widen(x)\n"
+                .to_string();
+        let file = File::new(source.clone());
+        let uri = Url::parse("file:///tmp/main.sail").unwrap();
+        let files = [(&uri, &file)];
+        let select = |text: &str| {
+            let start = source.rfind(text).unwrap();
+            Range::new(
+                file.source.position_at(start),
+                file.source.position_at(start + text.len()),
+            )
+        };
+
+        assert_eq!(
+            expr_type_in_range(&files, &uri, &file, select("widen(x)")).as_deref(),
+            Some("bits(16)")
+        );
+        assert_eq!(
+            expr_type_in_range(&files, &uri, &file, select("x")).as_deref(),
+            Some("bits(8)")
+        );
+        assert_eq!(
+            expr_type_in_range(&files, &uri, &file, select("-> bits(16) =")),
+            None
+        );
+    }
+
+    #[test]
+    fn shows_bitfield_layout_of_register_type() {
+        let source = "bitfield Mstatus : bits(64) = { SD : 63, MPP : 12 .. 11 }\nregister mstatus : Mstatus\nfunction f() = mstatus\n".to_string();
+        let file = File::new(source.clone());
+        let uri = Url::parse("file:///tmp/main.sail").unwrap();
+        let offset = source.rfind("mstatus").unwrap();
+        let pos = file.source.position_at(offset);
+
+        let hover = hover_for_symbol(
+            std::iter::once((&uri, &file)),
+            &uri,
+            &file,
+            pos,
+            Range::new(pos, file.source.position_at(offset + "mstatus".len())),
+            "mstatus",
+        )
+        .expect("hover");
+        let markdown = hover_markdown(hover);
+        assert!(markdown.contains("**register** **mstatus**"));
+        assert!(markdown.contains("**bitfield** `Mstatus`\n- `SD` : 63\n- `MPP` : 12 .. 11"));
     }
 
     #[test]
@@ -710,7 +1061,10 @@ overload op = {add, sub}
         )
         .expect("hover");
         let markdown = hover_markdown(hover);
-        assert_eq!(markdown.trim(), "```sail\n'n\n```");
+        assert_eq!(
+            markdown.trim(),
+            "```sail\n'n\n```\n\n___\n\ntype variable, in scope of `f`"
+        );
     }
 
     #[test]
@@ -786,6 +1140,28 @@ overload op = {add, sub}
         assert!(markdown.contains("instantiated as"));
         assert!(markdown.contains("bits(32) -> bits(32)"));
     }
+
+    #[test]
+    fn names_scope_of_type_variable() {
+        let source = "val pad : forall 'n. bits('n) -> bits('n)\n".to_string();
+        let file = File::new(source.clone());
+        let uri = Url::parse("file:///tmp/main.sail").unwrap();
+        let offset = source.rfind("'n").unwrap();
+        let pos = file.source.position_at(offset);
+
+        let hover = hover_for_symbol(
+            std::iter::once((&uri, &file)),
+            &uri,
+            &file,
+            pos,
+            Range::new(pos, file.source.position_at(offset + 2)),
+            "'n",
+        )
+        .expect("hover");
+        let markdown = hover_markdown(hover);
+        assert!(markdown.contains("```sail\n'n\n```"));
+        assert!(markdown.contains("type variable, in scope of `pad`"));
+    }
 }
 
 // ---------------------------------------------------------------------------
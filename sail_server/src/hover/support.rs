@@ -3,7 +3,7 @@ use crate::symbols::calls::ast_call_at_position;
 use crate::symbols::find_callable_signature;
 use crate::typecheck;
 use sail_parser::{Decl, DeclKind, Expr, Literal};
-use tower_lsp::lsp_types::{Position, Url};
+use tower_lsp::lsp_types::{Position, Range, Url};
 
 fn span_text<'a>(file: &'a File, span: sail_parser::Span) -> &'a str {
     file.source.text()[span.start..span.end].trim()
@@ -97,6 +97,24 @@ fn token_range_for_offsets(
     start_idx.zip(end_idx)
 }
 
+/// The type of the expression selected by `range`, or `None` when the
+/// selection is not an expression whose type can be worked out.
+pub(crate) fn expr_type_in_range<'a>(
+    files: &[(&'a Url, &'a File)],
+    current_uri: &Url,
+    current_file: &File,
+    range: Range,
+) -> Option<String> {
+    let tokens = current_file.tokens.as_deref()?;
+    let (start_idx, end_idx) = token_range_for_offsets(
+        tokens,
+        current_file.source.offset_at(&range.start),
+        current_file.source.offset_at(&range.end),
+    )?;
+    let expr = sail_parser::parse_expr_fragment(tokens, start_idx, end_idx)?;
+    infer_expr_type_text(files, current_uri, current_file, &expr)
+}
+
 pub(crate) fn infer_expr_type_text<'a>(
     files: &[(&'a Url, &'a File)],
     current_uri: &Url,
@@ -0,0 +1,48 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, OnceLock};
+
+/// Log files larger than this are moved aside to `<path>.old` on startup.
+const MAX_LOG_FILE_BYTES: u64 = 8 * 1024 * 1024;
+
+static LOG_FILE: OnceLock<Mutex<File>> = OnceLock::new();
+
+/// Write a line to the server log: the `--log-file`, or stderr without one.
+macro_rules! server_log {
+    ($($arg:tt)*) => {
+        $crate::logging::write_line(format_args!($($arg)*))
+    };
+}
+pub(crate) use server_log;
+
+/// Send the server log to `path` for the rest of the process. Logs from
+/// earlier sessions are kept, up to one rotated `<path>.old` once the file
+/// grows past `MAX_LOG_FILE_BYTES`.
+pub(crate) fn init_log_file(path: &Path) -> std::io::Result<()> {
+    if std::fs::metadata(path).is_ok_and(|meta| meta.len() > MAX_LOG_FILE_BYTES) {
+        let mut old = path.as_os_str().to_owned();
+        old.push(".old");
+        std::fs::rename(path, old)?;
+    }
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    writeln!(
+        file,
+        "--- sail_server {} (pid {}) ---",
+        env!("CARGO_PKG_VERSION"),
+        std::process::id()
+    )?;
+    let _ = LOG_FILE.set(Mutex::new(file));
+    Ok(())
+}
+
+pub(crate) fn write_line(message: std::fmt::Arguments) {
+    match LOG_FILE.get() {
+        Some(file) => {
+            if let Ok(mut file) = file.lock() {
+                let _ = writeln!(file, "{message}");
+            }
+        }
+        None => eprintln!("{message}"),
+    }
+}
@@ -1,18 +1,22 @@
 mod actions;
 mod backend;
 mod completion;
+mod config;
 mod diagnostics;
 mod formatting;
 mod handlers;
 mod hover;
 mod inlay_hints;
+mod logging;
 mod semantic_tokens;
 mod state;
 mod symbols;
+mod transport;
 mod typecheck;
 
 use backend::Backend;
-use tower_lsp::{LspService, Server};
+use tower_lsp::LspService;
+use transport::Options;
 
 // Re-exports used by tests (via `use super::*` in tests.rs).
 #[cfg(test)]
@@ -21,29 +25,40 @@ pub(crate) use actions::{
     quick_fix_for_diagnostic, resolve_code_action_edit_from_data, sail_source_fix_all_kind,
 };
 #[cfg(test)]
-pub(crate) use backend::{SAIL_BUILTINS, SAIL_KEYWORDS};
+pub(crate) use backend::{RefreshSupport, State};
 #[cfg(test)]
-pub(crate) use completion::{build_completion_items, completion_prefix};
+pub(crate) use completion::{
+    add_completion_label_details, build_completion_items, completion_prefix, in_numeric_literal,
+    in_type_expression, include_path_completions, match_arm_completions, plain_call_completions,
+};
+#[cfg(test)]
+pub(crate) use config::{Config, DiagnosticsScope};
 #[cfg(test)]
-pub(crate) use diagnostics::{document_diagnostic_report_for_file, workspace_diagnostic_report};
+pub(crate) use diagnostics::{
+    apply_overrides, document_diagnostic_report_for_file, workspace_diagnostic_report,
+};
 #[cfg(test)]
 pub(crate) use formatting::{
-    document_links_for_file, format_document_text, linked_editing_ranges_for_position,
-    make_selection_range, range_format_document_edits, range_len,
+    conditional_directive_blocks, document_links_for_file, format_document_text,
+    format_workspace_edits, include_link_target, linked_editing_ranges_for_position,
+    make_selection_range, matching_brace_position, option_directive_flags,
+    range_format_document_edits, range_len,
 };
 #[cfg(test)]
-pub(crate) use state::File;
+pub(crate) use state::files::{find_sail_binary, project_file_list};
+#[cfg(test)]
+pub(crate) use state::{scan_folders, File};
 #[cfg(test)]
-pub(crate) use symbols::analysis::Parameter;
+pub(crate) use symbols::analysis::{document_symbol_tree, Parameter};
 #[cfg(test)]
 pub(crate) use symbols::{
-    code_lens_title, code_lenses_for_file, collect_callable_signatures,
-    collect_implementation_counts, collect_reference_counts, find_call_at_position,
-    function_snippet, implementation_locations, parse_named_type, reference_locations,
-    rename_edits, resolve_symbol_at, resolve_workspace_symbol, signature_help_for_position,
-    symbol_declaration_locations, symbol_definition_locations, symbol_spans_for_file,
-    type_alias_edges, type_name_candidates_at_position, type_subtypes, type_supertypes,
-    typed_bindings, will_rename_file_edits,
+    code_lens_title, code_lenses_for_file, collect_callable_signatures, field_access_definitions,
+    find_call_at_position, function_snippet, implementation_locations, operator_decls_at,
+    parse_named_type, reference_locations, rename_edits, rename_preview, rename_workspace_edit,
+    resolve_symbol_at, resolve_workspace_symbol, signature_help_for_position,
+    symbol_declaration_locations, symbol_definition_locations, symbol_index_dump,
+    symbol_spans_for_file, type_alias_edges, type_name_candidates_at_position, type_subtypes,
+    type_supertypes, typed_bindings, will_rename_file_edits,
 };
 #[cfg(test)]
 pub(crate) use std::collections::hash_map::HashMap;
@@ -59,9 +74,30 @@ mod tests;
 
 #[tokio::main]
 async fn main() {
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
+    let options = match Options::from_args(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("sail_server: {err}");
+            std::process::exit(2);
+        }
+    };
+    if let Some(path) = options.log_file {
+        if let Err(err) = logging::init_log_file(&path) {
+            eprintln!(
+                "sail_server: cannot open log file {}: {err}",
+                path.display()
+            );
+        }
+    }
 
-    let (service, socket) = LspService::new(Backend::new_with_client);
-    Server::new(stdin, stdout, socket).serve(service).await;
+    let (service, socket) = LspService::build(Backend::new_with_client)
+        .custom_method("sail-lsp/dumpSymbols", Backend::dump_symbols)
+        .custom_method("sail-lsp/renamePreview", Backend::rename_preview)
+        .custom_method("sail-lsp/typeOf", Backend::type_of)
+        .custom_method("sail-lsp/reindex", Backend::reindex)
+        .finish();
+    if let Err(err) = options.transport.serve(service, socket).await {
+        logging::server_log!("sail_server: {err}");
+        std::process::exit(1);
+    }
 }
@@ -1,10 +1,15 @@
-use tower_lsp::lsp_types::{Diagnostic as LspDiagnostic, Position, TextDocumentContentChangeEvent};
+use tower_lsp::lsp_types::{
+    Diagnostic as LspDiagnostic, Position, TextDocumentContentChangeEvent, Url,
+};
 
 use super::TextDocument;
-use crate::diagnostics::{compute_parse_diagnostics, compute_semantic_diagnostics, Diagnostic};
-use crate::symbols::{add_parsed_definitions, CallableSignature};
+use crate::diagnostics::{
+    compute_parse_diagnostics, compute_semantic_diagnostics, unresolved_include_diagnostics,
+    Diagnostic,
+};
+use crate::symbols::{add_parsed_definitions, operator_decls, CallableSignature, OperatorDecl};
 use chumsky::Parser;
-use std::{cmp::Ordering, collections::HashMap, sync::Arc, sync::Mutex};
+use std::{cmp::Ordering, collections::HashMap, path::Path, sync::Arc, sync::Mutex};
 
 fn best_parsed(
     core_ast: Option<&sail_parser::core_ast::SourceFile>,
@@ -38,6 +43,9 @@ pub struct File {
     pub ref_counts: HashMap<String, usize>,
     pub impl_counts: HashMap<String, usize>,
 
+    // Cached fixity and `overload operator` declarations.
+    pub operator_decls: Vec<OperatorDecl>,
+
     // Parse and semantic diagnostics that are available without type checking.
     base_diagnostics: Vec<Diagnostic>,
     // Number of leading entries in `base_diagnostics` that are parse-only
@@ -49,6 +57,10 @@ pub struct File {
     // Cached LSP diagnostics to avoid repeated allocation.
     cached_lsp_diagnostics: Mutex<Option<Vec<LspDiagnostic>>>,
 
+    // Cached missing-`$include` diagnostics, so the file system is checked
+    // once per version rather than on every diagnostics pass.
+    cached_include_diagnostics: Mutex<Option<Vec<LspDiagnostic>>>,
+
     // Disk-indexed files skip eager type checking to keep workspace scans shallow.
     eager_type_check: bool,
 }
@@ -65,11 +77,15 @@ impl Clone for File {
             signature_index: self.signature_index.clone(),
             ref_counts: self.ref_counts.clone(),
             impl_counts: self.impl_counts.clone(),
+            operator_decls: self.operator_decls.clone(),
             base_diagnostics: self.base_diagnostics.clone(),
             parse_diagnostics_len: self.parse_diagnostics_len,
             cached_lsp_diagnostics: Mutex::new(
                 self.cached_lsp_diagnostics.lock().unwrap().clone(),
             ),
+            cached_include_diagnostics: Mutex::new(
+                self.cached_include_diagnostics.lock().unwrap().clone(),
+            ),
             eager_type_check: self.eager_type_check,
         }
     }
@@ -96,25 +112,27 @@ impl File {
             signature_index: HashMap::new(),
             ref_counts: HashMap::new(),
             impl_counts: HashMap::new(),
+            operator_decls: Vec::new(),
             base_diagnostics: Vec::new(),
             parse_diagnostics_len: 0,
             cached_lsp_diagnostics: Mutex::new(None),
+            cached_include_diagnostics: Mutex::new(None),
             eager_type_check,
         };
         f.parse();
         f
     }
 
-    pub fn update(&mut self, changes: Vec<TextDocumentContentChangeEvent>) {
-        for change in &changes {
-            self.source.update(change);
-        }
+    pub fn update(&mut self, changes: Vec<TextDocumentContentChangeEvent>) -> Result<(), String> {
+        self.source.apply_changes(&changes)?;
 
         self.parse();
+        Ok(())
     }
 
     pub fn parse(&mut self) {
         *self.cached_lsp_diagnostics.lock().unwrap() = None;
+        self.forget_include_checks();
         let text = self.source.text();
         let result = sail_parser::lexer().parse(text);
         let lex_errors = result.errors().cloned().collect::<Vec<_>>();
@@ -140,6 +158,7 @@ impl File {
         self.definitions = definitions;
         self.signature_index = crate::symbols::build_signature_index(self);
         self.build_count_caches();
+        self.operator_decls = operator_decls(self);
         // Record where parse diagnostics end and semantic diagnostics begin so
         // we can later replace just the semantic portion with workspace-aware
         // versions in `recompute_diagnostics_with_workspace`.
@@ -205,6 +224,11 @@ impl File {
         }
     }
 
+    /// Whether lexing or parsing reported any errors.
+    pub fn has_syntax_errors(&self) -> bool {
+        self.parse_diagnostics_len > 0
+    }
+
     pub fn parsed(&self) -> Option<&sail_parser::ParsedFile> {
         self.parsed.as_ref()
     }
@@ -226,6 +250,22 @@ impl File {
         self.type_check = type_check;
     }
 
+    /// Warnings for `$include` targets that do not exist, as of the last
+    /// check. See `forget_include_checks`.
+    pub fn include_diagnostics(&self, uri: &Url, library_dir: Option<&Path>) -> Vec<LspDiagnostic> {
+        self.cached_include_diagnostics
+            .lock()
+            .unwrap()
+            .get_or_insert_with(|| unresolved_include_diagnostics(uri, self, library_dir))
+            .clone()
+    }
+
+    /// Check `$include` targets again next time, after files were created or
+    /// deleted.
+    pub fn forget_include_checks(&self) {
+        *self.cached_include_diagnostics.lock().unwrap() = None;
+    }
+
     pub fn lsp_diagnostics(&self) -> Vec<LspDiagnostic> {
         let mut cache = self.cached_lsp_diagnostics.lock().unwrap();
         if let Some(ref cached) = *cache {
@@ -262,7 +302,9 @@ impl File {
     }
 
     pub fn token_at(&self, position: Position) -> Option<&(sail_parser::Token, sail_parser::Span)> {
-        let offset = self.source.offset_at(&position);
+        // Positions past the end of a line or of the file name no token, rather
+        // than whatever ends the line they would be clamped to.
+        let offset = self.source.checked_offset_at(&position)?;
         let tokens = self.tokens.as_deref()?;
 
         // LSP cursors are often reported at token boundaries; try exact offset first,
@@ -297,6 +339,31 @@ mod tests {
         assert!(best_parsed(None).is_none());
     }
 
+    #[test]
+    fn byte_order_mark_does_not_shift_positions() {
+        let file =
+            super::File::new("\u{feff}function f() = 1\r\nfunction g() = f()\r\n".to_string());
+
+        assert!(file.lsp_diagnostics().is_empty());
+        let g = file.source.position_at(file.definitions["g"]);
+        assert_eq!((g.line, g.character), (1, 9));
+    }
+
+    #[test]
+    fn positions_past_line_or_file_end_have_no_token() {
+        let file = super::File::new("let a = 1\nlet b = a\n".to_string());
+        let token_text = |line, character| {
+            file.token_at(tower_lsp::lsp_types::Position::new(line, character))
+                .map(|(_, span)| &file.source.text()[span.start..span.end])
+        };
+
+        assert_eq!(token_text(1, 9), Some("a"));
+        assert_eq!(token_text(0, 10), None);
+        assert_eq!(token_text(0, u32::MAX), None);
+        assert_eq!(token_text(5, 0), None);
+        assert_eq!(token_text(u32::MAX, 3), None);
+    }
+
     #[test]
     fn lazy_files_skip_eager_type_check() {
         let source = "function id(x) = x\n";
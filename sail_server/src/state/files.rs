@@ -2,9 +2,15 @@
 // every 30 seconds.
 
 use super::File;
+use crate::logging::server_log;
 use std::{
     collections::{HashMap, HashSet},
+    ffi::OsString,
     fs,
+    path::{Component, Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+    thread,
 };
 use tower_lsp::lsp_types::Url;
 use walkdir::WalkDir;
@@ -12,62 +18,283 @@ use walkdir::WalkDir;
 #[derive(Default)]
 pub struct Files {
     folders: HashSet<Url>,
+    library_folder: Option<Url>,
+    /// Folders scanned without their subfolders.
+    shallow_folders: HashSet<Url>,
+    /// Folders holding opened files, which are all that lazy indexing scans.
+    opened_roots: HashSet<Url>,
     files: HashMap<Url, File>,
 }
 
-pub fn scan_folders(folders: HashSet<Url>) -> HashMap<Url, File> {
-    let mut files = HashMap::new();
+/// Load the `.sail` files under `folders`, or those their project files list.
+/// Folders in `shallow` are not descended into. With `follow_symlinks`, linked
+/// directories are walked too; files keep the path they were reached by,
+/// which is the path editors open them under.
+pub fn scan_folders(
+    folders: HashSet<Url>,
+    shallow: &HashSet<Url>,
+    follow_symlinks: bool,
+) -> HashMap<Url, File> {
+    let mut paths = Vec::new();
 
     for folder in folders {
         if folder.scheme() != "file" {
             continue;
         }
         if let Ok(path) = folder.to_file_path() {
-            for entry in WalkDir::new(path) {
+            if let Some(listed) = project_source_files(&path) {
+                for path in listed {
+                    match file_url(&folder, &path) {
+                        Some(url) => paths.push((url, path)),
+                        None => server_log!("Error converting path to string: {}", path.display()),
+                    }
+                }
+                continue;
+            }
+            let max_depth = if shallow.contains(&folder) {
+                1
+            } else {
+                usize::MAX
+            };
+            for entry in WalkDir::new(path)
+                .follow_links(follow_symlinks)
+                .max_depth(max_depth)
+            {
                 match entry {
                     Ok(entry) => {
                         if entry.file_type().is_file()
                             && entry.path().extension() == Some("sail".as_ref())
                         {
-                            let path = entry.path();
-                            match fs::read_to_string(path) {
-                                Ok(source) => {
-                                    let file = File::new_lazy(source);
-                                    match path.to_str() {
-                                        Some(path_str) => {
-                                            let mut url = folder.clone();
-                                            // TODO: This is a hack to get around Windows paths and
-                                            // a bug in Url::set_path. https://github.com/servo/rust-url/issues/864
-                                            let mut path_windows = path_str.replace("\\", "/");
-                                            if !path_windows.starts_with('/') {
-                                                path_windows.insert(0, '/');
-                                            }
-                                            url.set_path(&path_windows);
-                                            files.insert(url, file);
-                                        }
-                                        None => {
-                                            eprintln!(
-                                                "Error converting path to string: {}",
-                                                path.display()
-                                            );
-                                        }
-                                    }
-                                }
-                                Err(e) => {
-                                    eprintln!("Error reading file {}: {:?}", path.display(), e);
+                            let path = entry.into_path();
+                            match file_url(&folder, &path) {
+                                Some(url) => paths.push((url, path)),
+                                None => {
+                                    server_log!(
+                                        "Error converting path to string: {}",
+                                        path.display()
+                                    );
                                 }
                             }
                         }
                     }
                     Err(e) => {
-                        eprintln!("Error scanning folder: {:?}", e);
+                        server_log!("Error scanning folder: {:?}", e);
                     }
                 }
             }
         }
     }
 
-    files
+    load_files(paths)
+}
+
+/// The `*.sail_project` files directly inside `folder`, in name order.
+fn project_files_in(folder: &Path) -> Vec<PathBuf> {
+    let mut projects = fs::read_dir(folder)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension() == Some("sail_project".as_ref()) && path.is_file())
+        .collect::<Vec<_>>();
+    projects.sort();
+    projects
+}
+
+/// The source files listed by the project files in `folder`, in the order
+/// they are listed, or `None` when there is no project file to go by and the
+/// folder should be walked instead.
+fn project_source_files(folder: &Path) -> Option<Vec<PathBuf>> {
+    let mut seen = HashSet::new();
+    let mut listed = Vec::new();
+    for project in project_files_in(folder) {
+        let text = match fs::read_to_string(&project) {
+            Ok(text) => text,
+            Err(e) => {
+                server_log!("Error reading project file {}: {:?}", project.display(), e);
+                continue;
+            }
+        };
+        let base = project.parent().unwrap_or(folder);
+        for file in project_file_list(&text) {
+            let path = normalize_path(&base.join(file));
+            if seen.insert(path.clone()) {
+                listed.push(path);
+            }
+        }
+    }
+    (!listed.is_empty()).then_some(listed)
+}
+
+/// The `.sail` paths named in a `.sail_project` file, in order. Module
+/// structure and `if`/`else` conditions are not evaluated, so files from
+/// every branch are listed; paths that interpolate `$VARIABLES` are skipped.
+pub fn project_file_list(text: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    for line in strip_block_comments(text).lines() {
+        let line = line.split("//").next().unwrap_or_default();
+        words.extend(
+            line.split(|c: char| c.is_whitespace() || matches!(c, ',' | '{' | '}' | '"'))
+                .filter(|word| word.ends_with(".sail") && !word.contains('$'))
+                .map(str::to_string),
+        );
+    }
+    words
+}
+
+fn strip_block_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = match rest[start + 2..].find("*/") {
+            Some(end) => &rest[start + 2 + end + 2..],
+            None => "",
+        };
+    }
+    out.push_str(rest);
+    out
+}
+
+/// The `sail` executable, found once: on `PATH`, else in an opam switch
+/// (`~/.opam/*/bin`) or `/usr/local/bin`.
+pub fn sail_binary() -> Option<&'static Path> {
+    static BINARY: OnceLock<Option<PathBuf>> = OnceLock::new();
+    BINARY
+        .get_or_init(|| {
+            let home = std::env::var_os("HOME").map(PathBuf::from);
+            let found = find_sail_binary(std::env::var_os("PATH"), home.as_deref());
+            match &found {
+                Some(path) => server_log!("Using sail at {}", path.display()),
+                None => server_log!(
+                    "sail not found on PATH, in ~/.opam/*/bin or /usr/local/bin; \
+                     set SAIL_DIR to resolve $include <...> files"
+                ),
+            }
+            found
+        })
+        .as_deref()
+}
+
+pub fn find_sail_binary(path_var: Option<OsString>, home: Option<&Path>) -> Option<PathBuf> {
+    let name = format!("sail{}", std::env::consts::EXE_SUFFIX);
+    let mut dirs = path_var
+        .map(|paths| std::env::split_paths(&paths).collect::<Vec<_>>())
+        .unwrap_or_default();
+    if let Some(Ok(switches)) = home.map(|home| fs::read_dir(home.join(".opam"))) {
+        let mut switches = switches
+            .filter_map(|entry| Some(entry.ok()?.path().join("bin")))
+            .collect::<Vec<_>>();
+        switches.sort();
+        dirs.extend(switches);
+    }
+    dirs.push(PathBuf::from("/usr/local/bin"));
+    dirs.into_iter()
+        .map(|dir| dir.join(&name))
+        .find(|path| path.is_file())
+}
+
+/// Sail's library directory, where `$include <...>` files live. Taken from
+/// `SAIL_DIR`, or `sail --dir` when that isn't set, and looked up only once.
+pub fn sail_library_dir() -> Option<&'static Path> {
+    static LIBRARY_DIR: OnceLock<Option<PathBuf>> = OnceLock::new();
+    LIBRARY_DIR
+        .get_or_init(|| {
+            let sail_dir = match std::env::var_os("SAIL_DIR") {
+                Some(dir) => PathBuf::from(dir),
+                None => {
+                    let output = Command::new(sail_binary()?).arg("--dir").output().ok()?;
+                    if !output.status.success() {
+                        return None;
+                    }
+                    PathBuf::from(String::from_utf8(output.stdout).ok()?.trim())
+                }
+            };
+            let lib = sail_dir.join("lib");
+            lib.is_dir().then_some(lib)
+        })
+        .as_deref()
+}
+
+/// Load a `file://` document straight from disk, for one that is neither
+/// open nor indexed.
+pub fn read_file(uri: &Url) -> Option<File> {
+    if uri.scheme() != "file" {
+        return None;
+    }
+    let source = fs::read_to_string(uri.to_file_path().ok()?).ok()?;
+    Some(File::new_lazy(source))
+}
+
+/// `path` with `.` and `..` components resolved lexically, so the same file
+/// reached as `a/../b.sail` and `b.sail` gets one URL. Symlinks are left
+/// alone: the file need not exist, and editors report the path as opened.
+pub fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !normalized.pop() {
+                    normalized.push(component);
+                }
+            }
+            _ => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+fn file_url(folder: &Url, path: &Path) -> Option<Url> {
+    let path_str = path.to_str()?;
+    let mut url = folder.clone();
+    // TODO: This is a hack to get around Windows paths and
+    // a bug in Url::set_path. https://github.com/servo/rust-url/issues/864
+    let mut path_windows = path_str.replace("\\", "/");
+    if !path_windows.starts_with('/') {
+        path_windows.insert(0, '/');
+    }
+    url.set_path(&path_windows);
+    Some(url)
+}
+
+// Reading and parsing dominates the scan on large trees, so spread the files
+// over a few worker threads and merge their results at the end.
+fn load_files(paths: Vec<(Url, PathBuf)>) -> HashMap<Url, File> {
+    let workers = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1)
+        .min(paths.len())
+        .max(1);
+    let chunk_size = paths.len().div_ceil(workers).max(1);
+
+    thread::scope(|scope| {
+        let handles: Vec<_> = paths
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || load_chunk(chunk)))
+            .collect();
+
+        let mut files = HashMap::with_capacity(paths.len());
+        for handle in handles {
+            match handle.join() {
+                Ok(loaded) => files.extend(loaded),
+                Err(_) => server_log!("Error loading files: worker thread panicked"),
+            }
+        }
+        files
+    })
+}
+
+fn load_chunk(chunk: &[(Url, PathBuf)]) -> Vec<(Url, File)> {
+    chunk
+        .iter()
+        .filter_map(|(url, path)| match fs::read_to_string(path) {
+            Ok(source) => Some((url.clone(), File::new_lazy(source))),
+            Err(e) => {
+                server_log!("Error reading file {}: {:?}", path.display(), e);
+                None
+            }
+        })
+        .collect()
 }
 
 impl Files {
@@ -77,6 +304,7 @@ impl Files {
 
     pub fn remove_folder(&mut self, folder: &Url) {
         self.folders.remove(folder);
+        self.shallow_folders.remove(folder);
     }
 
     pub fn add_file(&mut self, url: Url, file: File) {
@@ -102,4 +330,125 @@ impl Files {
     pub fn folders(&self) -> &HashSet<Url> {
         &self.folders
     }
+
+    /// Stand in for a workspace folder when a file is opened without one:
+    /// the closest folder above `url` with a `.sail_project` file, or else
+    /// just the folder `url` is in, without its subfolders. Returns the folder.
+    pub fn add_implicit_folder(&mut self, url: &Url) -> Option<Url> {
+        if url.scheme() != "file" {
+            return None;
+        }
+        let path = url.to_file_path().ok()?;
+        let parent = path.parent()?;
+        let folder = match parent
+            .ancestors()
+            .find(|dir| !project_files_in(dir).is_empty())
+        {
+            Some(project) => Url::from_directory_path(project).ok()?,
+            None => {
+                let folder = Url::from_directory_path(parent).ok()?;
+                self.shallow_folders.insert(folder.clone());
+                folder
+            }
+        };
+        self.folders.insert(folder.clone());
+        Some(folder)
+    }
+
+    /// Folders that scans should not descend into.
+    pub fn shallow_folders(&self) -> &HashSet<Url> {
+        &self.shallow_folders
+    }
+
+    /// Index `folder` alongside the workspace without treating it as part of
+    /// the project.
+    pub fn set_library_folder(&mut self, folder: Url) {
+        self.library_folder = Some(folder);
+    }
+
+    /// Whether `url` is under a workspace folder whose file list comes from a
+    /// `.sail_project` file rather than from walking the folder.
+    pub fn is_under_project(&self, url: &Url) -> bool {
+        let Ok(path) = url.to_file_path() else {
+            return false;
+        };
+        self.folders.iter().any(|folder| {
+            folder.to_file_path().is_ok_and(|folder| {
+                path.starts_with(&folder) && !project_files_in(&folder).is_empty()
+            })
+        })
+    }
+
+    /// Whether `url` is inside one of the workspace folders. Folder URIs may
+    /// lack a trailing slash, so paths are compared by component.
+    pub fn is_in_folders(&self, url: &Url) -> bool {
+        let Ok(path) = url.to_file_path() else {
+            return false;
+        };
+        self.folders.iter().any(|folder| {
+            folder
+                .to_file_path()
+                .is_ok_and(|folder| path.starts_with(folder))
+        })
+    }
+
+    /// Whether `url` was indexed from Sail's library folder.
+    pub fn is_library_file(&self, url: &Url) -> bool {
+        self.library_folder
+            .as_ref()
+            .is_some_and(|folder| url.as_str().starts_with(folder.as_str()))
+    }
+
+    /// Record the folder that `url` belongs to for lazy indexing: the closest
+    /// one with a `.sail_project` file, looking no higher than its workspace
+    /// folder, or else the folder it is in. Returns whether that folder is
+    /// new, so it still needs scanning.
+    pub fn add_opened_root(&mut self, url: &Url) -> bool {
+        if self
+            .opened_roots
+            .iter()
+            .any(|root| url.as_str().starts_with(root.as_str()))
+        {
+            return false;
+        }
+        let Ok(path) = url.to_file_path() else {
+            return false;
+        };
+        let Some(parent) = path.parent() else {
+            return false;
+        };
+        let boundary = self
+            .folders
+            .iter()
+            .filter_map(|folder| folder.to_file_path().ok())
+            .find(|folder| parent.starts_with(folder));
+        let root = match &boundary {
+            Some(boundary) => parent
+                .ancestors()
+                .take_while(|dir| dir.starts_with(boundary))
+                .find(|dir| !project_files_in(dir).is_empty())
+                .unwrap_or(parent),
+            None => parent,
+        };
+        match Url::from_directory_path(root) {
+            Ok(root) => self.opened_roots.insert(root),
+            Err(()) => false,
+        }
+    }
+
+    /// Every folder a workspace scan should walk: the workspace folders, or
+    /// with `lazy` only the roots of opened files, plus Sail's library folder
+    /// when `include_library` is set.
+    pub fn scan_roots(&self, include_library: bool, lazy: bool) -> HashSet<Url> {
+        let roots = if lazy {
+            &self.opened_roots
+        } else {
+            &self.folders
+        };
+        roots
+            .iter()
+            .chain(self.library_folder.as_ref().filter(|_| include_library))
+            .cloned()
+            .collect()
+    }
 }
@@ -3,5 +3,5 @@ pub mod files;
 pub mod text_document;
 
 pub use file::File;
-pub use files::{scan_folders, Files};
+pub use files::{normalize_path, read_file, sail_library_dir, scan_folders, Files};
 pub use text_document::TextDocument;
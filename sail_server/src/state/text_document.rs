@@ -14,6 +14,7 @@ pub struct TextDocument {
 
 impl TextDocument {
     pub fn new(content: String) -> Self {
+        let content = strip_bom(content);
         let line_offsets = compute_line_offsets(&content, true, 0);
         Self {
             content,
@@ -74,20 +75,108 @@ impl TextDocument {
             );
         } else {
             // Just completely change the text.
-            self.content = change.text.clone();
+            self.content = strip_bom(change.text.clone());
             self.line_offsets = compute_line_offsets(&self.content, true, 0);
         }
     }
 
-    // Convert a row/column position to a byte index.
+    // Apply a batch of changes from one `didChange` notification. Changes are
+    // sequential, each relative to the text left by the previous one, but
+    // editors send multi-cursor edits and pastes bottom-up: each range ends
+    // before the previous one starts, so all of them still address the
+    // original text. Such runs are spliced in one pass and the line index is
+    // rebuilt once, instead of shifting the whole document per change.
+    //
+    // A range that ends before it starts cannot be applied; the whole batch is
+    // then rejected and the document left untouched, so the caller can report
+    // that it no longer matches the client's copy.
+    pub fn apply_changes(
+        &mut self,
+        changes: &[TextDocumentContentChangeEvent],
+    ) -> Result<(), String> {
+        // Everything before a full-text replacement is irrelevant.
+        let start = changes
+            .iter()
+            .rposition(|change| change.range.is_none())
+            .unwrap_or(0);
+        let changes = &changes[start..];
+        if let Some(range) = changes
+            .iter()
+            .filter_map(|change| change.range)
+            .find(|range| range.start > range.end)
+        {
+            return Err(format!(
+                "change range {}:{}-{}:{} ends before it starts",
+                range.start.line, range.start.character, range.end.line, range.end.character
+            ));
+        }
+
+        let mut rest = changes;
+        while !rest.is_empty() {
+            let run = descending_run_len(rest);
+            if run > 1 {
+                self.splice_descending(&rest[..run]);
+            } else {
+                self.update(&rest[0]);
+            }
+            rest = &rest[run.max(1)..];
+        }
+        Ok(())
+    }
+
+    fn splice_descending(&mut self, changes: &[TextDocumentContentChangeEvent]) {
+        let mut edits = changes
+            .iter()
+            .filter_map(|change| {
+                let range = change.range?;
+                let begin = self.offset_at(&range.start);
+                let end = self.offset_at(&range.end).max(begin);
+                Some((begin, end, change.text.as_str()))
+            })
+            .collect::<Vec<_>>();
+        // Later changes sit earlier in the text.
+        edits.reverse();
+
+        let inserted = edits.iter().map(|(_, _, text)| text.len()).sum::<usize>();
+        let mut content = String::with_capacity(self.content.len() + inserted);
+        let mut copied = 0;
+        for (begin, end, text) in edits {
+            content.push_str(&self.content[copied..begin]);
+            content.push_str(text);
+            copied = end;
+        }
+        content.push_str(&self.content[copied..]);
+
+        self.line_offsets = compute_line_offsets(&content, true, 0);
+        self.content = content;
+    }
+
+    // Convert a row/column position to a byte index. Characters past the end
+    // of a line clamp to the line end, and lines past the end of the document
+    // clamp to the document end.
     pub fn offset_at(&self, position: &LspPosition) -> usize {
         let line_begin = self.line_start(position.line as usize);
-        let line_end = self.line_start(position.line as usize + 1);
-        let line = &self.content[line_begin..line_end];
+        let line = self.line_text(position.line as usize);
 
         line_begin + character_to_line_offset(line, position.character as usize)
     }
 
+    // Like `offset_at`, but positions past the end of their line or of the
+    // document have no offset instead of being clamped.
+    pub fn checked_offset_at(&self, position: &LspPosition) -> Option<usize> {
+        let line_index = position.line as usize;
+        if line_index >= self.line_offsets.len() {
+            return None;
+        }
+        let line = self.line_text(line_index);
+        let character = position.character as usize;
+        if character > line.encode_utf16().count() {
+            return None;
+        }
+
+        Some(self.line_start(line_index) + character_to_line_offset(line, character))
+    }
+
     // Convert a byte index to a row/column position.
     pub fn position_at(&self, offset: usize) -> LspPosition {
         // Clamp to valid range.
@@ -122,6 +211,13 @@ impl TextDocument {
             .unwrap_or_else(|| self.content.len())
     }
 
+    // The text of a line without its terminator.
+    fn line_text(&self, line_index: usize) -> &str {
+        let line_begin = self.line_start(line_index);
+        let line_end = self.line_start(line_index + 1);
+        self.content[line_begin..line_end].trim_end_matches(['\r', '\n'])
+    }
+
     // Given a byte offset, what is the corresponding character?
     fn position_at_line(&self, line: usize, offset: usize) -> usize {
         let line_start = self.line_offsets[line];
@@ -134,6 +230,15 @@ impl TextDocument {
     }
 }
 
+// Editors don't count a UTF-8 byte-order mark as part of the document, so drop
+// it to keep line 0 positions (and the lexer) in agreement with the client.
+fn strip_bom(content: String) -> String {
+    match content.strip_prefix('\u{feff}') {
+        Some(stripped) => stripped.to_string(),
+        None => content,
+    }
+}
+
 // Given a UTF-16 codepoint offset in a bit of text, convert it to a byte offset.
 // Out-of-bounds offsets just return line.len().
 fn character_to_line_offset(line: &str, character: usize) -> usize {
@@ -149,6 +254,25 @@ fn character_to_line_offset(line: &str, character: usize) -> usize {
     line.len()
 }
 
+// How many leading `changes` have ranges that each end at or before the
+// start of the previous one.
+fn descending_run_len(changes: &[TextDocumentContentChangeEvent]) -> usize {
+    let Some(mut previous) = changes.first().and_then(|change| change.range) else {
+        return 0;
+    };
+    let mut len = 1;
+    for change in &changes[1..] {
+        match change.range {
+            Some(range) if range.start <= range.end && range.end <= previous.start => {
+                previous = range;
+                len += 1;
+            }
+            _ => break,
+        }
+    }
+    len
+}
+
 fn compute_line_offsets(text: &str, is_at_line_start: bool, text_offset: usize) -> Vec<usize> {
     // VSCode treats `\r\n` or `\n` *or* `\r` as a single line end.
     let mut line_offsets = Vec::new();
@@ -261,6 +385,87 @@ mod test {
         assert_eq!(TextDocument::new(text).line_count(), 3);
     }
 
+    fn change(start: (u32, u32), end: (u32, u32), text: &str) -> TextDocumentContentChangeEvent {
+        TextDocumentContentChangeEvent {
+            range: Some(LspRange::new(
+                LspPosition::new(start.0, start.1),
+                LspPosition::new(end.0, end.1),
+            )),
+            range_length: None,
+            text: text.to_string(),
+        }
+    }
+
+    fn apply_one_by_one(text: &str, changes: &[TextDocumentContentChangeEvent]) -> TextDocument {
+        let mut document = TextDocument::new(text.to_string());
+        for change in changes {
+            document.update(change);
+        }
+        document
+    }
+
+    #[test]
+    fn batched_changes_match_sequential_application() {
+        let text = "let a = 1\nlet b = 2\r\nlet c = 3\nlet d = 4\n";
+        let batches = [
+            // Multi-cursor style: bottom-up, non-overlapping.
+            vec![
+                change((3, 4), (3, 5), "delta"),
+                change((2, 8), (3, 0), "30\n"),
+                change((1, 4), (1, 5), "beta\r\nlet b2"),
+                change((0, 0), (0, 0), "// head\n"),
+                change((0, 0), (0, 0), "// first\n"),
+            ],
+            // Top-down, with each range already shifted by the edits before it.
+            vec![
+                change((0, 4), (0, 5), "alpha"),
+                change((1, 4), (1, 5), "b\nlet b3"),
+                change((4, 0), (4, 9), ""),
+            ],
+            // A full replacement part way through the batch.
+            vec![
+                change((0, 0), (0, 3), "var"),
+                TextDocumentContentChangeEvent {
+                    range: None,
+                    range_length: None,
+                    text: "x\ny\n".to_string(),
+                },
+                change((1, 0), (1, 1), "z"),
+                change((0, 0), (0, 1), "w"),
+            ],
+        ];
+
+        for changes in batches {
+            let expected = apply_one_by_one(text, &changes);
+            let mut document = TextDocument::new(text.to_string());
+            document.apply_changes(&changes).expect("valid changes");
+
+            assert_eq!(document.text(), expected.text());
+            assert_eq!(document.line_offsets, expected.line_offsets);
+        }
+    }
+
+    #[test]
+    fn inverted_change_range_rejects_the_batch() {
+        let text = "let a = 1\nlet b = 2\n";
+        let mut document = TextDocument::new(text.to_string());
+        let changes = [change((1, 4), (1, 5), "c"), change((0, 8), (0, 4), "oops")];
+        assert!(document.apply_changes(&changes).is_err());
+        assert_eq!(document.text(), text);
+
+        // An earlier full replacement discards the bad change.
+        let changes = [
+            change((0, 8), (0, 4), "oops"),
+            TextDocumentContentChangeEvent {
+                range: None,
+                range_length: None,
+                text: "x\n".to_string(),
+            },
+        ];
+        assert_eq!(document.apply_changes(&changes), Ok(()));
+        assert_eq!(document.text(), "x\n");
+    }
+
     #[test]
     fn get_text_range() {
         let text = "12345\n12345\n12345".to_string();
@@ -323,6 +528,13 @@ mod test {
         // assert_eq!(document.offset_at(&LspPosition::new(0, -3)), 0);
         // assert_eq!(document.offset_at(&LspPosition::new(1, -3)), text.len());
 
+        assert_eq!(
+            document.checked_offset_at(&LspPosition::new(0, 11)),
+            Some(11)
+        );
+        assert_eq!(document.checked_offset_at(&LspPosition::new(0, 12)), None);
+        assert_eq!(document.checked_offset_at(&LspPosition::new(1, 0)), None);
+
         // invalid offsets
         // assert_eq!(document.position_at(-1), LspPosition::new(0, 0));
         assert_eq!(
@@ -335,6 +547,44 @@ mod test {
         );
     }
 
+    #[test]
+    fn clamps_past_end_of_line_before_terminator() {
+        let document = TextDocument::new("ab\r\ncd\nef".to_string());
+
+        assert_eq!(document.offset_at(&LspPosition::new(0, 9)), 2);
+        assert_eq!(document.offset_at(&LspPosition::new(1, 9)), 6);
+        assert_eq!(document.checked_offset_at(&LspPosition::new(1, 2)), Some(6));
+        assert_eq!(document.checked_offset_at(&LspPosition::new(1, 3)), None);
+        assert_eq!(document.checked_offset_at(&LspPosition::new(2, 2)), Some(9));
+        assert_eq!(document.checked_offset_at(&LspPosition::new(3, 0)), None);
+    }
+
+    #[test]
+    fn strips_byte_order_mark() {
+        let document = TextDocument::new("\u{feff}ABC\nDEF".to_string());
+        assert_eq!(document.text(), "ABC\nDEF");
+        assert_eq!(document.offset_at(&LspPosition::new(0, 1)), 1);
+        assert_eq!(document.position_at(5), LspPosition::new(1, 1));
+    }
+
+    #[test]
+    fn crlf_positions() {
+        let text = "ABC\r\nDEF\r\n".to_string();
+        let document = TextDocument::new(text);
+        assert_eq!(document.line_count(), 3);
+        assert_eq!(document.offset_at(&LspPosition::new(1, 0)), 5);
+        assert_eq!(document.offset_at(&LspPosition::new(1, 2)), 7);
+        assert_eq!(document.position_at(7), LspPosition::new(1, 2));
+        assert_eq!(document.position_at(10), LspPosition::new(2, 0));
+        assert_eq!(
+            document.text_range(&LspRange::new(
+                LspPosition::new(0, 1),
+                LspPosition::new(1, 1)
+            )),
+            "BC\r\nD"
+        );
+    }
+
     // Full updates.
 
     #[test]
@@ -348,6 +598,18 @@ mod test {
         assert_eq!(document.text(), "efg456");
     }
 
+    #[test]
+    fn full_update_strips_byte_order_mark() {
+        let mut document = TextDocument::new("abc".to_string());
+        document.update(&TextDocumentContentChangeEvent {
+            text: "\u{feff}def\nghi".to_string(),
+            range: None,
+            range_length: None,
+        });
+        assert_eq!(document.text(), "def\nghi");
+        assert_eq!(document.offset_at(&LspPosition::new(1, 0)), 4);
+    }
+
     #[test]
     fn several_full_content_updates() {
         let mut document = TextDocument::new("abc123".to_string());
@@ -676,6 +938,23 @@ mod test {
         assert_valid_line_numbers(&document);
     }
 
+    #[test]
+    fn crlf_multi_line_insert() {
+        let mut document = TextDocument::new("foo\r\nbar\r\nbaz".to_string());
+        document.update(&TextDocumentContentChangeEvent {
+            text: "1\r\n2\r\n".to_string(),
+            range: Some(LspRange::new(
+                LspPosition::new(1, 0),
+                LspPosition::new(1, 0),
+            )),
+            range_length: None,
+        });
+        assert_eq!(document.text(), "foo\r\n1\r\n2\r\nbar\r\nbaz");
+        assert_eq!(document.line_count(), 5);
+        assert_eq!(document.offset_at(&LspPosition::new(3, 0)), 11);
+        assert_eq!(document.offset_at(&LspPosition::new(4, 2)), 18);
+    }
+
     #[test]
     fn multi_line_append() {
         let mut document = TextDocument::new("foooo\nbar\nbaz".to_string());
@@ -9,6 +9,8 @@ pub(crate) struct SymbolDecl {
     pub(crate) kind: SymbolKind,
     pub(crate) detail: &'static str,
     pub(crate) offset: usize,
+    /// The item this symbol belongs to, e.g. the mapping a clause extends.
+    pub(crate) container: Option<String>,
 }
 
 #[derive(Clone)]
@@ -33,6 +35,110 @@ pub(crate) fn token_symbol_key(token: &sail_parser::Token) -> Option<String> {
     }
 }
 
+/// A declaration that gives a user-defined operator its meaning.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct OperatorDecl {
+    pub(crate) operator: String,
+    /// The whole `infix 4 <_u` / `overload operator <_u = {...}` item.
+    pub(crate) item_span: Span,
+    /// Overload members; empty for fixity declarations.
+    pub(crate) members: Vec<String>,
+    pub(crate) is_fixity: bool,
+}
+
+/// Fixity declarations and `overload operator` definitions in `file`.
+pub(crate) fn operator_decls(file: &File) -> Vec<OperatorDecl> {
+    use sail_parser::core_ast::DefinitionKind;
+
+    let Some(ast) = file.core_ast() else {
+        return Vec::new();
+    };
+    let text = file.source.text();
+    ast.defs
+        .iter()
+        .filter_map(|(def, span)| match &def.kind {
+            DefinitionKind::Fixity(fixity) => Some(OperatorDecl {
+                operator: fixity.operator.0.clone(),
+                item_span: *span,
+                members: Vec::new(),
+                is_fixity: true,
+            }),
+            DefinitionKind::Named(named)
+                if named.kind == sail_parser::NamedDefKind::Overload
+                    && named.name.0 == "operator" =>
+            {
+                // The operator itself is not kept by the parser; it is the
+                // text between `operator` and `=`.
+                let operator = text
+                    .get(named.name.1.end..named.value_span?.start)?
+                    .trim()
+                    .trim_end_matches('=')
+                    .trim();
+                (!operator.is_empty()).then(|| OperatorDecl {
+                    operator: operator.to_string(),
+                    item_span: *span,
+                    members: named.members.iter().map(|m| m.0.clone()).collect(),
+                    is_fixity: false,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// The longest of `operators` spelled out in `text` over `offset`, with its
+/// span. Operators are lexed as several tokens (`<_u` is `<` then `_u`), so
+/// this matches on the source text instead.
+pub(crate) fn operator_at<'o, I>(text: &str, offset: usize, operators: I) -> Option<(&'o str, Span)>
+where
+    I: IntoIterator<Item = &'o str>,
+{
+    let is_symbol = |c: char| "!%&*+-./:<=>@^|~".contains(c);
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    // `-` must not match inside `->`, nor `<_u` inside `<_uv`.
+    let standalone = |op: &str, start: usize| {
+        let before = text[..start].chars().next_back();
+        let after = text[start + op.len()..].chars().next();
+        let glued_ident = op.ends_with(is_ident) && after.is_some_and(is_ident);
+        !(before.is_some_and(is_symbol) || after.is_some_and(is_symbol) || glued_ident)
+    };
+    operators
+        .into_iter()
+        .filter(|op| !op.is_empty())
+        .filter_map(|op| {
+            let first = (offset + 1).saturating_sub(op.len());
+            (first..=offset)
+                .find(|&start| {
+                    text.get(start..start + op.len()) == Some(op) && standalone(op, start)
+                })
+                .map(|start| (op, Span::new(start, start + op.len())))
+        })
+        .max_by_key(|(op, _)| op.len())
+}
+
+/// Span of the top-level definition containing `offset`, if any.
+pub(crate) fn enclosing_item_span(file: &File, offset: usize) -> Option<Span> {
+    file.core_ast()?
+        .defs
+        .iter()
+        .map(|(_, span)| *span)
+        .find(|span| span.start <= offset && offset <= span.end)
+}
+
+/// Name of the top-level definition containing `offset`, if any.
+pub(crate) fn enclosing_item_name(file: &File, offset: usize) -> Option<String> {
+    let item = enclosing_item_span(file, offset)?;
+    file.parsed()?
+        .decls
+        .iter()
+        .find(|decl| {
+            decl.scope == sail_parser::Scope::TopLevel
+                && item.start <= decl.span.start
+                && decl.span.end <= item.end
+        })
+        .map(|decl| decl.name.clone())
+}
+
 #[cfg(test)]
 pub(crate) fn add_definitions(
     tokens: &[(sail_parser::Token, Span)],
@@ -94,6 +200,9 @@ pub(crate) fn extract_symbol_decls(file: &File) -> Vec<SymbolDecl> {
             let (kind, detail) = match decl.kind {
                 sail_parser::DeclKind::Function => (SymbolKind::FUNCTION, "function"),
                 sail_parser::DeclKind::Value => (SymbolKind::FUNCTION, "value"),
+                sail_parser::DeclKind::Mapping if is_mapping_clause(decl) => {
+                    (SymbolKind::FUNCTION, "mapping clause")
+                }
                 sail_parser::DeclKind::Mapping => (SymbolKind::FUNCTION, "mapping"),
                 sail_parser::DeclKind::Overload => (SymbolKind::FUNCTION, "overload"),
                 sail_parser::DeclKind::Register => (SymbolKind::VARIABLE, "register"),
@@ -115,43 +224,159 @@ pub(crate) fn extract_symbol_decls(file: &File) -> Vec<SymbolDecl> {
                 kind,
                 detail,
                 offset: decl.span.start,
+                container: is_mapping_clause(decl).then(|| format!("mapping {}", decl.name)),
             })
         })
         .collect()
 }
 
-/// Build a hierarchical DocumentSymbol tree. Enum members become children of
-/// their parent enum; all other top-level decls are roots.
+#[allow(deprecated)] // DocumentSymbol.deprecated is deprecated in the LSP type
+fn member_symbol(
+    file: &File,
+    name: &sail_parser::Spanned<String>,
+    span: Span,
+    kind: SymbolKind,
+    detail: &str,
+) -> DocumentSymbol {
+    DocumentSymbol {
+        name: name.0.clone(),
+        detail: Some(detail.to_string()),
+        kind,
+        tags: None,
+        deprecated: None,
+        range: range_from_span(file, span),
+        selection_range: range_from_span(file, name.1),
+        children: None,
+    }
+}
+
+/// Child symbols declared inside a named definition's body: enum members,
+/// struct fields, union variants and bitfield fields.
+fn named_def_children(
+    file: &File,
+    def: &sail_parser::core_ast::NamedDefinition,
+) -> Vec<DocumentSymbol> {
+    use sail_parser::core_ast::NamedDefDetail;
+
+    match &def.detail {
+        Some(NamedDefDetail::Enum { members, .. }) => members
+            .iter()
+            .map(|(member, span)| {
+                member_symbol(
+                    file,
+                    &member.name,
+                    *span,
+                    SymbolKind::ENUM_MEMBER,
+                    "enum member",
+                )
+            })
+            .collect(),
+        Some(NamedDefDetail::Struct { fields }) => fields
+            .iter()
+            .map(|(field, span)| {
+                member_symbol(file, &field.name, *span, SymbolKind::FIELD, "field")
+            })
+            .collect(),
+        Some(NamedDefDetail::Union { variants }) => variants
+            .iter()
+            .map(|(variant, span)| {
+                member_symbol(
+                    file,
+                    &variant.name,
+                    *span,
+                    SymbolKind::ENUM_MEMBER,
+                    "variant",
+                )
+            })
+            .collect(),
+        Some(NamedDefDetail::Bitfield { fields }) => fields
+            .iter()
+            .map(|(field, span)| {
+                member_symbol(file, &field.name, *span, SymbolKind::FIELD, "field")
+            })
+            .collect(),
+        None if def.kind == sail_parser::NamedDefKind::Enum => def
+            .members
+            .iter()
+            .map(|member| {
+                member_symbol(
+                    file,
+                    member,
+                    member.1,
+                    SymbolKind::ENUM_MEMBER,
+                    "enum member",
+                )
+            })
+            .collect(),
+        None => Vec::new(),
+    }
+}
+
+/// Build a hierarchical DocumentSymbol tree. Each top-level item's `range`
+/// covers its whole definition while `selection_range` covers just the name.
+/// Enum members, struct fields, union variants and bitfield fields become
+/// children of the definition that declares them; `enum clause` and
+/// `union clause` members, and `mapping clause`s, are parented under their
+/// scattered head when it is in the same file.
 #[allow(deprecated)] // DocumentSymbol.deprecated is deprecated in the LSP type
 pub(crate) fn document_symbol_tree(file: &File) -> Vec<DocumentSymbol> {
     let Some(parsed) = file.parsed() else {
         return Vec::new();
     };
 
-    // First pass: collect top-level items and their full spans.
-    let item_spans: Vec<(usize, usize)> = if let Some(ast) = file.core_ast() {
-        ast.defs
-            .iter()
-            .map(|(_, span)| (span.start, span.end))
-            .collect()
-    } else {
-        Vec::new()
-    };
+    // First pass: collect top-level items, their full spans and any members
+    // declared inside them.
+    let mut item_spans: Vec<(usize, usize)> = Vec::new();
+    let mut item_children: HashMap<usize, Vec<DocumentSymbol>> = HashMap::new();
+    let mut clause_children: Vec<(String, DocumentSymbol)> = Vec::new();
+    if let Some(ast) = file.core_ast() {
+        for (def, span) in &ast.defs {
+            item_spans.push((span.start, span.end));
+            match &def.kind {
+                sail_parser::core_ast::DefinitionKind::Named(named) => {
+                    let children = named_def_children(file, named);
+                    if !children.is_empty() {
+                        item_children.insert(named.name.1.start, children);
+                    }
+                }
+                sail_parser::core_ast::DefinitionKind::ScatteredClause(clause) => {
+                    let (kind, detail) = match clause.kind {
+                        sail_parser::ScatteredClauseKind::Enum => {
+                            (SymbolKind::ENUM_MEMBER, "enum member")
+                        }
+                        sail_parser::ScatteredClauseKind::Union => {
+                            (SymbolKind::ENUM_MEMBER, "variant")
+                        }
+                    };
+                    clause_children.push((
+                        clause.name.0.clone(),
+                        member_symbol(file, &clause.member, *span, kind, detail),
+                    ));
+                }
+                _ => {}
+            }
+        }
+    }
 
     let mut roots: Vec<DocumentSymbol> = Vec::new();
-    // Track enum positions for parenting members.
-    let mut enum_indices: HashMap<String, usize> = HashMap::new();
+    // Scattered heads by name, for parenting `enum clause`/`union clause`
+    // members and `mapping clause`s.
+    let mut scattered_heads: HashMap<String, usize> = HashMap::new();
 
     for decl in &parsed.decls {
+        // Members are produced from the core AST above.
         if decl.scope == sail_parser::Scope::Local
-            && !matches!(decl.kind, sail_parser::DeclKind::EnumMember)
+            || matches!(decl.kind, sail_parser::DeclKind::EnumMember)
         {
             continue;
         }
 
-        let (kind, detail) = match decl.kind {
+        let (kind, detail_label) = match decl.kind {
             sail_parser::DeclKind::Function => (SymbolKind::FUNCTION, "function"),
             sail_parser::DeclKind::Value => (SymbolKind::FUNCTION, "value"),
+            sail_parser::DeclKind::Mapping if is_mapping_clause(decl) => {
+                (SymbolKind::FUNCTION, "mapping clause")
+            }
             sail_parser::DeclKind::Mapping => (SymbolKind::FUNCTION, "mapping"),
             sail_parser::DeclKind::Overload => (SymbolKind::FUNCTION, "overload"),
             sail_parser::DeclKind::Register => (SymbolKind::VARIABLE, "register"),
@@ -182,38 +407,129 @@ pub(crate) fn document_symbol_tree(file: &File) -> Vec<DocumentSymbol> {
             })
             .unwrap_or(selection_range);
 
+        if decl.is_scattered
+            && decl.role == sail_parser::DeclRole::Declaration
+            && matches!(
+                decl.kind,
+                sail_parser::DeclKind::Enum
+                    | sail_parser::DeclKind::Union
+                    | sail_parser::DeclKind::Mapping
+            )
+        {
+            scattered_heads.insert(decl.name.clone(), roots.len());
+        }
+
+        let detail = if is_mapping_clause(decl) {
+            None
+        } else {
+            declared_type_detail(file, decl)
+        };
         let symbol = DocumentSymbol {
             name: decl.name.clone(),
-            detail: Some(detail.to_string()),
+            detail: Some(detail.unwrap_or_else(|| detail_label.to_string())),
             kind,
             tags: None,
             deprecated: None,
             range: full_range,
             selection_range,
-            children: Some(Vec::new()),
+            children: Some(item_children.remove(&decl.span.start).unwrap_or_default()),
         };
+        if is_mapping_clause(decl) {
+            clause_children.push((decl.name.clone(), symbol));
+        } else {
+            roots.push(symbol);
+        }
+    }
 
-        if decl.kind == sail_parser::DeclKind::EnumMember {
-            // Try to parent under the most recent enum.
-            let parented = enum_indices
-                .values()
-                .copied()
-                .max()
-                .and_then(|idx| roots.get_mut(idx))
-                .and_then(|parent| parent.children.as_mut());
-            if let Some(children) = parented {
-                children.push(symbol);
-                continue;
+    let mut orphans = Vec::new();
+    for (parent, symbol) in clause_children {
+        let parent = scattered_heads
+            .get(&parent)
+            .and_then(|idx| roots.get_mut(*idx))
+            .and_then(|root| root.children.as_mut());
+        match parent {
+            Some(children) => children.push(symbol),
+            None => orphans.push(symbol),
+        }
+    }
+    if !orphans.is_empty() {
+        roots.extend(orphans);
+        roots.sort_by_key(|symbol| symbol.range.start);
+    }
+
+    roots
+}
+
+/// The type written at a declaration, for outline details: the signature
+/// from a `val` or typed `function`/`mapping` head, or the `: type` of a
+/// register or top-level binding. Whitespace is collapsed so multi-line
+/// signatures fit on one line.
+fn declared_type_detail(file: &File, decl: &sail_parser::Decl) -> Option<String> {
+    let ty = match decl.kind {
+        sail_parser::DeclKind::Function
+        | sail_parser::DeclKind::Value
+        | sail_parser::DeclKind::Mapping => {
+            let label = &file.signature_index.get(&decl.name)?.label;
+            let rest = label_after_name(label, &decl.name)?;
+            match top_level_colon(rest) {
+                Some(colon) => rest[colon + 1..].to_string(),
+                // A `function f(x : int) -> int` head; an untyped head has
+                // nothing worth showing.
+                None if rest.contains("->") => rest.to_string(),
+                None => return None,
             }
         }
+        sail_parser::DeclKind::Register
+        | sail_parser::DeclKind::Let
+        | sail_parser::DeclKind::Var => {
+            let rest = file.source.text().get(decl.span.end..)?;
+            let line = rest.lines().next()?;
+            let ty = line.trim_start().strip_prefix(':')?;
+            ty.split(" = ").next()?.to_string()
+        }
+        _ => return None,
+    };
+    let ty = ty.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!ty.is_empty()).then_some(ty)
+}
 
-        if decl.kind == sail_parser::DeclKind::Enum {
-            enum_indices.insert(decl.name.clone(), roots.len());
+/// The part of a signature label after the declared name, skipping the
+/// leading keywords (`val`, `function`, ...) that might contain it.
+fn label_after_name<'l>(label: &'l str, name: &str) -> Option<&'l str> {
+    let is_ident = |c: char| c.is_alphanumeric() || c == '_' || c == '\'';
+    label.match_indices(name).find_map(|(start, _)| {
+        let end = start + name.len();
+        let before = label[..start].chars().next_back();
+        let after = label[end..].chars().next();
+        (before.is_some_and(char::is_whitespace) && !after.is_some_and(is_ident))
+            .then(|| &label[end..])
+    })
+}
+
+/// Offset of the first `:` outside brackets and string literals, which
+/// separates a name (or an extern binding such as `= pure "f"`) from its type.
+fn top_level_colon(text: &str) -> Option<usize> {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    for (idx, ch) in text.char_indices() {
+        match ch {
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth = depth.saturating_sub(1),
+            ':' if depth == 0 => return Some(idx),
+            _ => {}
         }
-        roots.push(symbol);
     }
+    None
+}
 
-    roots
+/// A `mapping clause` of a scattered mapping, as opposed to its head or a
+/// whole `mapping` definition.
+pub(crate) fn is_mapping_clause(decl: &sail_parser::Decl) -> bool {
+    decl.kind == sail_parser::DeclKind::Mapping
+        && decl.is_scattered
+        && decl.role == sail_parser::DeclRole::Definition
 }
 
 pub(crate) fn range_from_span(file: &File, span: Span) -> Range {
@@ -230,6 +546,13 @@ pub(crate) fn location_from_span(uri: &Url, file: &File, span: Span) -> Location
 pub(crate) fn builtin_docs(name: &str) -> Option<&'static str> {
     match name {
         "bits" => Some("`bits('n)` is a bitvector of length `'n`. It is one of the most fundamental types in Sail."),
+        "bitvector" => Some("`bitvector('n)` is a bitvector of length `'n`; `bits('n)` is its usual spelling."),
+        "vector" => Some("`vector('n, 'a)` is a vector of `'n` elements of type `'a`."),
+        "bit" => Some("`bit` is a single bit, `bitzero` or `bitone`."),
+        "range" => Some("`range('n, 'm)` is the type of integers from `'n` to `'m` inclusive."),
+        "atom" => Some("`atom('n)` is the singleton type of the integer `'n`; `int('n)` is its usual spelling."),
+        "implicit" => Some("`implicit('n)` is an integer argument that callers may leave out, inferred from the other argument types."),
+        "list" => Some("`list('a)` is a linked list of elements of type `'a`."),
         "int" => Some("`int` is an arbitrary-precision integer."),
         "nat" => Some("`nat` is a non-negative arbitrary-precision integer."),
         "bool" => Some("`bool` is a boolean type with values `true` and `false`."),
@@ -3,24 +3,15 @@ use crate::state::File;
 use std::collections::HashMap;
 use tower_lsp::lsp_types::{CodeLens, Range, SymbolKind, Url};
 
-pub(crate) fn collect_reference_counts(files: &[(&Url, &File)]) -> HashMap<String, usize> {
-    let mut counts = HashMap::<String, usize>::new();
-    for (_, file) in files {
-        for (name, count) in &file.ref_counts {
-            *counts.entry(name.clone()).or_insert(0) += count;
-        }
-    }
-    counts
-}
-
-pub(crate) fn collect_implementation_counts(files: &[(&Url, &File)]) -> HashMap<String, usize> {
-    let mut counts = HashMap::<String, usize>::new();
-    for (_, file) in files {
-        for (name, count) in &file.impl_counts {
-            *counts.entry(name.clone()).or_insert(0) += count;
-        }
-    }
-    counts
+fn count_in_files(
+    files: &[(&Url, &File)],
+    name: &str,
+    counts: impl Fn(&File) -> &HashMap<String, usize>,
+) -> usize {
+    files
+        .iter()
+        .map(|(_, file)| counts(file).get(name).copied().unwrap_or(0))
+        .sum()
 }
 
 fn pluralize(count: usize, singular: &str, plural: &str) -> String {
@@ -31,25 +22,25 @@ fn pluralize(count: usize, singular: &str, plural: &str) -> String {
     }
 }
 
-pub(crate) fn code_lens_title(data: &serde_json::Value) -> Option<String> {
+/// Title for an unresolved lens from [`code_lenses_for_file`]. Counting across
+/// the workspace happens here, on resolve, so listing lenses stays cheap.
+pub(crate) fn code_lens_title(data: &serde_json::Value, files: &[(&Url, &File)]) -> Option<String> {
     let kind = data.get("kind")?.as_str()?;
-    let count = data
-        .get("count")
-        .and_then(|v| v.as_u64())
-        .map(|n| n as usize)
-        .unwrap_or(0);
+    let name = data.get("name")?.as_str()?;
     match kind {
-        "refs" => Some(pluralize(count, "reference", "references")),
-        "impls" => Some(pluralize(count, "implementation", "implementations")),
+        "refs" => {
+            let count = count_in_files(files, name, |file| &file.ref_counts);
+            Some(pluralize(count, "reference", "references"))
+        }
+        "impls" => {
+            let count = count_in_files(files, name, |file| &file.impl_counts);
+            Some(pluralize(count, "implementation", "implementations"))
+        }
         _ => None,
     }
 }
 
-pub(crate) fn code_lenses_for_file(
-    file: &File,
-    ref_counts: &HashMap<String, usize>,
-    impl_counts: &HashMap<String, usize>,
-) -> Vec<CodeLens> {
+pub(crate) fn code_lenses_for_file(file: &File) -> Vec<CodeLens> {
     let mut out = Vec::new();
 
     for decl in extract_symbol_decls(file) {
@@ -60,26 +51,22 @@ pub(crate) fn code_lenses_for_file(
             file.source.position_at(decl.offset),
             file.source.position_at(decl.offset + decl.name.len()),
         );
-        let refs = ref_counts.get(&decl.name).copied().unwrap_or(0);
         out.push(CodeLens {
             range,
             command: None,
             data: Some(serde_json::json!({
                 "kind": "refs",
                 "name": decl.name,
-                "count": refs
             })),
         });
 
         if decl.kind == SymbolKind::FUNCTION {
-            let impls = impl_counts.get(&decl.name).copied().unwrap_or(0);
             out.push(CodeLens {
                 range,
                 command: None,
                 data: Some(serde_json::json!({
                     "kind": "impls",
                     "name": decl.name,
-                    "count": impls
                 })),
             });
 
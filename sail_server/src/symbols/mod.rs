@@ -6,24 +6,24 @@ pub mod references;
 
 pub(crate) use analysis::{
     add_parsed_definitions, build_signature_index, builtin_docs, collect_callable_signatures,
-    extract_comments, extract_symbol_decls, find_callable_signature, function_snippet,
-    inlay_param_name, instantiate_signature, token_is_close_bracket, token_is_open_bracket,
-    token_symbol_key, CallableSignature, Parameter,
+    enclosing_item_name, enclosing_item_span, extract_comments, extract_symbol_decls,
+    find_callable_signature, function_snippet, inlay_param_name, instantiate_signature,
+    location_from_span, operator_decls, token_is_close_bracket, token_is_open_bracket,
+    token_symbol_key, CallableSignature, OperatorDecl, Parameter,
 };
 pub(crate) use calls::{find_call_at_position, signature_help_for_position};
-pub(crate) use lenses::{
-    code_lens_title, code_lenses_for_file, collect_implementation_counts, collect_reference_counts,
-};
+pub(crate) use lenses::{code_lens_title, code_lenses_for_file};
 pub(crate) use navigation::{
-    call_edges_from, call_edges_to, call_hierarchy_item, implementation_locations,
-    parse_named_type, resolve_workspace_symbol, symbol_declaration_locations,
-    symbol_definition_locations, type_definition_locations, type_hierarchy_item,
-    type_name_candidates_at_position, type_subtypes, type_supertypes, typed_bindings,
-    will_rename_file_edits,
+    call_edges_from, call_edges_to, call_hierarchy_item, field_access_definitions,
+    implementation_locations, member_definition_locations, operator_decls_at, parse_named_type,
+    resolve_workspace_symbol, symbol_declaration_locations, symbol_definition_locations,
+    symbol_index_dump, type_definition_locations, type_hierarchy_item,
+    type_name_candidates_at_position, type_subtypes, type_supertypes, type_var_definition_location,
+    typed_bindings, will_rename_file_edits,
 };
 #[cfg(test)]
 pub(crate) use navigation::type_alias_edges;
 pub(crate) use references::{
-    normalize_validated_rename, reference_locations, rename_edits, resolve_symbol_at,
-    symbol_spans_for_file,
+    normalize_validated_rename, reference_locations, rename_edits, rename_preview,
+    rename_workspace_edit, resolve_symbol_at, symbol_spans_for_file,
 };
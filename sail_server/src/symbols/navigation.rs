@@ -1,11 +1,12 @@
 use super::analysis::{
-    extract_symbol_decls, find_callable_signature, location_from_span, range_from_span,
-    token_symbol_key,
+    extract_symbol_decls, find_callable_signature, location_from_span, operator_at,
+    range_from_span, token_symbol_key, OperatorDecl,
 };
+use super::references::resolve_symbol_at;
 use crate::state::File;
-use sail_parser::{DeclRole, Scope, Span};
+use sail_parser::{DeclRole, Scope, Span, SymbolOccurrenceKind};
 use std::cmp::Reverse;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 use tower_lsp::lsp_types::{
     CallHierarchyItem, Location, OneOf, Range, RenameFilesParams, SymbolKind, TextEdit,
     TypeHierarchyItem, Url, WorkspaceLocation, WorkspaceSymbol,
@@ -154,11 +155,31 @@ fn symbol_definition_spans(file: &File, symbol_key: &str) -> Vec<Span> {
         })
         .map(|decl| decl.span)
         .collect::<Vec<_>>();
+    let outcome = sail_parser::Token::KwOutcome;
+    spans.extend(keyword_name_spans(file, &outcome, symbol_key));
     spans.sort_unstable_by_key(|span| (span.start, span.end));
     spans.dedup();
     spans
 }
 
+/// Spans of `name` where it directly follows `keyword`. Outcomes and their
+/// `impl` clauses are found this way, since the parser skips `outcome` items
+/// and has no `impl` definitions.
+fn keyword_name_spans(file: &File, keyword: &sail_parser::Token, name: &str) -> Vec<Span> {
+    let Some(tokens) = file.tokens.as_deref() else {
+        return Vec::new();
+    };
+    tokens
+        .windows(2)
+        .filter_map(|pair| match pair {
+            [(token, _), (sail_parser::Token::Id(id), span)] if token == keyword && id == name => {
+                Some(*span)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
 fn type_decls_with_kind(file: &File) -> HashMap<String, (usize, SymbolKind)> {
     let mut out = HashMap::new();
     let Some(parsed) = file.parsed() else {
@@ -195,6 +216,86 @@ pub(crate) fn type_alias_edges(file: &File) -> Vec<(String, String)> {
         .collect()
 }
 
+/// Struct fields and union variants as `(container, embedded type)` edges, so
+/// a record's "supertypes" are the types it is built from.
+pub(crate) fn type_embedding_edges(file: &File) -> Vec<(String, String)> {
+    use sail_parser::core_ast::{DefinitionKind, NamedDefDetail, UnionPayload};
+
+    let Some(ast) = file.core_ast() else {
+        return Vec::new();
+    };
+    let mut edges = Vec::new();
+    for (def, _) in &ast.defs {
+        let DefinitionKind::Named(named) = &def.kind else {
+            continue;
+        };
+        let mut embedded = Vec::new();
+        match &named.detail {
+            Some(NamedDefDetail::Struct { fields }) => {
+                for (field, _) in fields {
+                    collect_named_types(&field.ty.0, &mut embedded);
+                }
+            }
+            Some(NamedDefDetail::Union { variants }) => {
+                for (variant, _) in variants {
+                    match &variant.payload {
+                        UnionPayload::Type(ty) => collect_named_types(&ty.0, &mut embedded),
+                        UnionPayload::Struct { fields } => {
+                            for (field, _) in fields {
+                                collect_named_types(&field.ty.0, &mut embedded);
+                            }
+                        }
+                    }
+                }
+            }
+            _ => continue,
+        }
+        embedded.sort();
+        embedded.dedup();
+        let container = &named.name.0;
+        edges.extend(
+            embedded
+                .into_iter()
+                .filter(|ty| ty != container)
+                .map(|ty| (container.clone(), ty)),
+        );
+    }
+    edges
+}
+
+fn collect_named_types(ty: &sail_parser::core_ast::TypeExpr, out: &mut Vec<String>) {
+    use sail_parser::core_ast::TypeExpr;
+
+    match ty {
+        TypeExpr::Named(name) => out.push(name.clone()),
+        TypeExpr::App { callee, args } => {
+            out.push(callee.0.clone());
+            for (arg, _) in args {
+                collect_named_types(arg, out);
+            }
+        }
+        TypeExpr::Tuple(items) | TypeExpr::Set(items) => {
+            for (item, _) in items {
+                collect_named_types(item, out);
+            }
+        }
+        TypeExpr::Register(inner) => collect_named_types(&inner.0, out),
+        TypeExpr::Effect { ty, .. } | TypeExpr::Prefix { ty, .. } => {
+            collect_named_types(&ty.0, out)
+        }
+        TypeExpr::Forall { body, .. } | TypeExpr::Existential { body, .. } => {
+            collect_named_types(&body.0, out)
+        }
+        _ => {}
+    }
+}
+
+fn type_hierarchy_edges(file: &File) -> Vec<(String, String)> {
+    let mut edges = type_alias_edges(file);
+    edges.extend(type_embedding_edges(file));
+    edges
+}
+
 pub(crate) fn type_hierarchy_item<'a, I>(
     files: I,
     uri_hint: &Url,
@@ -242,7 +343,7 @@ where
     let files = files.into_iter().collect::<Vec<_>>();
     let names: HashSet<String> = files
         .iter()
-        .flat_map(|(_, file)| type_alias_edges(file))
+        .flat_map(|(_, file)| type_hierarchy_edges(file))
         .filter_map(|(sub, sup)| if sub == name { Some(sup) } else { None })
         .collect();
 
@@ -259,7 +360,7 @@ where
     let files = files.into_iter().collect::<Vec<_>>();
     let names: HashSet<String> = files
         .iter()
-        .flat_map(|(_, file)| type_alias_edges(file))
+        .flat_map(|(_, file)| type_hierarchy_edges(file))
         .filter_map(|(sub, sup)| if sup == name { Some(sub) } else { None })
         .collect();
 
@@ -326,7 +427,6 @@ pub(crate) fn typed_bindings(file: &File) -> HashMap<String, String> {
 }
 
 pub(crate) fn parse_named_type(text: &str) -> Option<String> {
-    let lower = text.to_ascii_lowercase();
     let builtins = [
         "int", "nat", "bool", "string", "unit", "bits", "bit", "real", "list", "vector", "atom",
         "implicit", "order", "type",
@@ -343,9 +443,8 @@ pub(crate) fn parse_named_type(text: &str) -> Option<String> {
                 j += 1;
             }
             let name = chars[i..j].iter().collect::<String>();
-            if !builtins.contains(&name.to_ascii_lowercase().as_str())
-                && !builtins.contains(&lower.as_str())
-            {
+            // Case-sensitive, like Sail: a user type `Bits` is not `bits`.
+            if !builtins.contains(&name.as_str()) {
                 return Some(name);
             }
             i = j;
@@ -383,12 +482,28 @@ where
     locations
 }
 
+/// Where `name` is implemented: every function/mapping body or clause, every
+/// `union clause`/`enum clause` of a scattered type, and every `impl` of an
+/// outcome. Anything else falls back to its definition.
 pub(crate) fn implementation_locations<'a, I>(files: I, uri_hint: &Url, name: &str) -> Vec<Location>
 where
     I: IntoIterator<Item = (&'a Url, &'a File)>,
 {
+    let files = files.into_iter().collect::<Vec<_>>();
     let mut locations = Vec::new();
-    for (uri, file) in files {
+    for &(uri, file) in &files {
+        if let Some(ast) = file.core_ast() {
+            for (def, _) in &ast.defs {
+                if let sail_parser::core_ast::DefinitionKind::ScatteredClause(clause) = &def.kind {
+                    if clause.name.0 == name {
+                        locations.push(location_from_span(uri, file, clause.member.1));
+                    }
+                }
+            }
+        }
+        for span in keyword_name_spans(file, &sail_parser::Token::KwImpl, name) {
+            locations.push(location_from_span(uri, file, span));
+        }
         let Some(parsed) = file.parsed() else {
             continue;
         };
@@ -422,9 +537,122 @@ where
             },
         )
     });
+    if locations.is_empty() {
+        return symbol_definition_locations(files, uri_hint, name);
+    }
     locations
 }
 
+/// For the field name of a `record.field` access, the struct fields it could
+/// name; `None` when the identifier at `span` is not a field access. Sail has
+/// no module-qualified names, so a dot after an expression is always a
+/// projection and must not resolve to an unrelated top-level `field`.
+pub(crate) fn field_access_definitions<'a, I>(
+    files: I,
+    file: &File,
+    span: Span,
+) -> Option<Vec<Location>>
+where
+    I: IntoIterator<Item = (&'a Url, &'a File)>,
+{
+    use sail_parser::core_ast::{DefinitionKind, NamedDefDetail, UnionPayload};
+
+    let text = file.source.text();
+    let before = text[..span.start].strip_suffix('.')?;
+    let projected = before
+        .chars()
+        .next_back()
+        .is_some_and(|c| c.is_alphanumeric() || matches!(c, '_' | '\'' | ')' | ']'));
+    if !projected {
+        return None;
+    }
+    let field = &text[span.start..span.end];
+
+    let mut locations = Vec::new();
+    for (uri, file) in files {
+        let Some(ast) = file.core_ast() else {
+            continue;
+        };
+        for (def, _) in &ast.defs {
+            let DefinitionKind::Named(named) = &def.kind else {
+                continue;
+            };
+            let mut fields = Vec::new();
+            match &named.detail {
+                Some(NamedDefDetail::Struct { fields: own }) => fields.extend(own),
+                Some(NamedDefDetail::Union { variants }) => {
+                    for (variant, _) in variants {
+                        if let UnionPayload::Struct { fields: own } = &variant.payload {
+                            fields.extend(own);
+                        }
+                    }
+                }
+                _ => continue,
+            }
+            locations.extend(
+                fields
+                    .into_iter()
+                    .filter(|(typed, _)| typed.name.0 == field)
+                    .map(|(typed, _)| location_from_span(uri, file, typed.name.1)),
+            );
+        }
+    }
+    Some(locations)
+}
+
+pub(crate) type OperatorSite<'a> = (&'a Url, &'a File, OperatorDecl);
+
+/// The user-defined operator under `offset` in `file`, with every fixity and
+/// `overload operator` declaration for it across `files`. Text in comments
+/// and string literals is not an operator use.
+pub(crate) fn operator_decls_at<'a, I>(
+    files: I,
+    file: &File,
+    offset: usize,
+) -> Option<(Span, Vec<OperatorSite<'a>>)>
+where
+    I: IntoIterator<Item = (&'a Url, &'a File)>,
+{
+    let tokens = file.tokens.as_deref()?;
+    let in_code = |offset: usize| {
+        tokens.iter().any(|(token, span)| {
+            span.start <= offset
+                && offset < span.end
+                && !matches!(
+                    token,
+                    sail_parser::Token::String(_)
+                        | sail_parser::Token::MultilineString(_)
+                        | sail_parser::Token::Directive { .. }
+                )
+        })
+    };
+    if !in_code(offset) {
+        return None;
+    }
+    let decls = files
+        .into_iter()
+        .flat_map(|(uri, file)| {
+            file.operator_decls
+                .iter()
+                .map(move |decl| (uri, file, decl.clone()))
+        })
+        .collect::<Vec<_>>();
+    let (operator, span) = operator_at(
+        file.source.text(),
+        offset,
+        decls.iter().map(|(_, _, decl)| decl.operator.as_str()),
+    )?;
+    if !(span.start..span.end).all(in_code) {
+        return None;
+    }
+    let operator = operator.to_string();
+    let decls = decls
+        .into_iter()
+        .filter(|(_, _, decl)| decl.operator == operator)
+        .collect();
+    Some((span, decls))
+}
+
 pub(crate) fn symbol_definition_locations<'a, I>(
     files: I,
     uri_hint: &Url,
@@ -433,8 +661,9 @@ pub(crate) fn symbol_definition_locations<'a, I>(
 where
     I: IntoIterator<Item = (&'a Url, &'a File)>,
 {
+    let files = files.into_iter().collect::<Vec<_>>();
     let mut definitions = files
-        .into_iter()
+        .iter()
         .flat_map(|(uri, file)| {
             symbol_definition_spans(file, symbol_key)
                 .into_iter()
@@ -450,9 +679,96 @@ where
             },
         )
     });
+
+    // A scattered mapping is defined by its clauses, but its `scattered
+    // mapping` head is where readers expect to land; the clauses follow.
+    let mut heads = files
+        .iter()
+        .flat_map(|(uri, file)| {
+            file.parsed()
+                .into_iter()
+                .flat_map(|parsed| parsed.decls.iter())
+                .filter(|decl| {
+                    decl.name == symbol_key
+                        && decl.kind == sail_parser::DeclKind::Mapping
+                        && decl.is_scattered
+                        && decl.role == DeclRole::Declaration
+                })
+                .map(move |decl| location_from_span(uri, file, decl.span))
+        })
+        .collect::<Vec<_>>();
+    if !heads.is_empty() {
+        heads.extend(definitions);
+        return heads;
+    }
+
+    // An overload is only a name for its members; offer each of them too.
+    let members = files
+        .iter()
+        .flat_map(|(_, file)| overload_members(file, symbol_key))
+        .collect::<Vec<_>>();
+    for location in member_definition_locations(files.iter().copied(), &members) {
+        if !definitions.contains(&location) {
+            definitions.push(location);
+        }
+    }
     definitions
 }
 
+fn overload_members(file: &File, symbol_key: &str) -> Vec<String> {
+    let (Some(parsed), Some(ast)) = (file.parsed(), file.core_ast()) else {
+        return Vec::new();
+    };
+    parsed
+        .decls
+        .iter()
+        .filter(|decl| decl.name == symbol_key && decl.kind == sail_parser::DeclKind::Overload)
+        .filter_map(|decl| {
+            sail_parser::find_named_members(ast, sail_parser::NamedDefKind::Overload, decl.span)
+        })
+        .flatten()
+        .map(|member| member.0.clone())
+        .collect()
+}
+
+/// Definitions of each of `members`, in order, such as the functions an
+/// overload stands for. Members with no body, like `val` externs, resolve to
+/// their declaration instead.
+pub(crate) fn member_definition_locations<'a, I>(files: I, members: &[String]) -> Vec<Location>
+where
+    I: IntoIterator<Item = (&'a Url, &'a File)>,
+{
+    let files = files.into_iter().collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    let mut locations = Vec::new();
+    for member in members {
+        if !seen.insert(member.as_str()) {
+            continue;
+        }
+        let definitions = files
+            .iter()
+            .flat_map(|(uri, file)| {
+                symbol_definition_spans(file, member)
+                    .into_iter()
+                    .map(move |span| location_from_span(uri, file, span))
+            })
+            .collect::<Vec<_>>();
+        if definitions.is_empty() {
+            let uri_hint = files.first().map(|(uri, _)| (*uri).clone());
+            if let Some(uri_hint) = uri_hint {
+                locations.extend(symbol_declaration_locations(
+                    files.iter().copied(),
+                    &uri_hint,
+                    member,
+                ));
+            }
+        } else {
+            locations.extend(definitions);
+        }
+    }
+    locations
+}
+
 pub(crate) fn symbol_declaration_locations<'a, I>(
     files: I,
     uri_hint: &Url,
@@ -531,6 +847,67 @@ where
     symbol
 }
 
+/// Binding site of the type variable under the cursor, e.g. in its `forall`.
+pub(crate) fn type_var_definition_location(
+    uri: &Url,
+    file: &File,
+    position: tower_lsp::lsp_types::Position,
+) -> Option<Location> {
+    let symbol = resolve_symbol_at(file, position)?;
+    if symbol.kind != SymbolOccurrenceKind::TypeVar {
+        return None;
+    }
+    Some(location_from_span(uri, file, symbol.target_span?))
+}
+
+/// Dump the symbol index as `{ name: [{ uri, range, kind }] }`, keeping only
+/// names containing `filter` (case-insensitive) when one is given.
+pub(crate) fn symbol_index_dump<'a, I>(files: I, filter: Option<&str>) -> serde_json::Value
+where
+    I: IntoIterator<Item = (&'a Url, &'a File)>,
+{
+    let filter = filter.map(str::to_ascii_lowercase);
+    let mut index: BTreeMap<String, Vec<(Url, Range, &'static str)>> = BTreeMap::new();
+    for (uri, file) in files {
+        for decl in extract_symbol_decls(file) {
+            if let Some(filter) = &filter {
+                if !decl.name.to_ascii_lowercase().contains(filter.as_str()) {
+                    continue;
+                }
+            }
+            let range = Range::new(
+                file.source.position_at(decl.offset),
+                file.source.position_at(decl.offset + decl.name.len()),
+            );
+            index
+                .entry(decl.name)
+                .or_default()
+                .push((uri.clone(), range, decl.detail));
+        }
+    }
+
+    let entries = index
+        .into_iter()
+        .map(|(name, mut locations)| {
+            locations.sort_by(|a, b| {
+                (a.0.as_str(), a.1.start.line, a.1.start.character).cmp(&(
+                    b.0.as_str(),
+                    b.1.start.line,
+                    b.1.start.character,
+                ))
+            });
+            let locations = locations
+                .into_iter()
+                .map(|(uri, range, kind)| {
+                    serde_json::json!({ "uri": uri, "range": range, "kind": kind })
+                })
+                .collect();
+            (name, serde_json::Value::Array(locations))
+        })
+        .collect();
+    serde_json::Value::Object(entries)
+}
+
 fn basename_from_uri(uri: &str) -> Option<String> {
     Url::parse(uri).ok().and_then(|url| {
         url.path_segments()
@@ -620,4 +997,25 @@ mod tests {
             )
         );
     }
+
+    #[test]
+    fn overload_definitions_include_members() {
+        let source = "val eq_int = pure \"eq_int\" : (int, int) -> bool\nfunction eq_bit(a, b) = true\noverload eq = {eq_bit, eq_int}\n".to_string();
+        let file = File::new(source.clone());
+        let uri = Url::parse("file:///tmp/main.sail").unwrap();
+
+        let definitions = symbol_definition_locations(std::iter::once((&uri, &file)), &uri, "eq");
+        let starts = definitions
+            .iter()
+            .map(|location| file.source.offset_at(&location.range.start))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            starts,
+            [
+                source.find("eq =").unwrap(),
+                source.find("eq_bit(").unwrap(),
+                source.find("eq_int =").unwrap(),
+            ]
+        );
+    }
 }
@@ -4,7 +4,10 @@ use super::analysis::location_from_span;
 use crate::state::File;
 use sail_parser::{Scope, Span, SymbolOccurrenceKind};
 use tower_lsp::jsonrpc::Result;
-use tower_lsp::lsp_types::{Location, Position, Range, TextEdit, Url};
+use tower_lsp::lsp_types::{
+    DocumentChanges, Location, OneOf, OptionalVersionedTextDocumentIdentifier, Position, Range,
+    TextDocumentEdit, TextEdit, Url, WorkspaceEdit,
+};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub(crate) struct ResolvedSymbol {
@@ -39,6 +42,9 @@ fn matches_symbol(
         return occurrence.scope == Some(Scope::TopLevel) && occurrence.name == symbol.name;
     }
 
+    // Exact comparison on purpose: Sail identifiers are case-sensitive, so
+    // `Foo` and `foo` are different symbols. Only search-style features
+    // (completion, workspace symbols) fold case.
     occurrence.name == symbol.name
 }
 
@@ -124,26 +130,116 @@ pub(crate) fn rename_edits<'a, I>(
 where
     I: IntoIterator<Item = (&'a Url, &'a File)>,
 {
-    let local_only = symbol.target_span.is_some() || symbol.kind == SymbolOccurrenceKind::TypeVar;
-    let mut changes: HashMap<Url, Vec<TextEdit>> = HashMap::new();
+    rename_spans(files, current_uri, symbol)
+        .into_iter()
+        .map(|(uri, file, spans)| {
+            let edits = spans
+                .into_iter()
+                .map(|span| TextEdit {
+                    range: Range::new(
+                        file.source.position_at(span.start),
+                        file.source.position_at(span.end),
+                    ),
+                    new_text: new_text.to_string(),
+                })
+                .collect();
+            (uri.clone(), edits)
+        })
+        .collect()
+}
 
-    for (uri, file) in files {
-        if local_only && uri != current_uri {
-            continue;
-        }
-        for (span, _) in symbol_spans_for_file(file, symbol, true) {
-            changes.entry(uri.clone()).or_default().push(TextEdit {
-                range: Range::new(
-                    file.source.position_at(span.start),
-                    file.source.position_at(span.end),
-                ),
-                new_text: new_text.to_string(),
-            });
+/// What a rename of `symbol` would touch, without a new name: a list of
+/// `{ uri, range, line }` entries, `line` being the text of the line each
+/// occurrence is on, ordered by file and position.
+pub(crate) fn rename_preview<'a, I>(
+    files: I,
+    current_uri: &Url,
+    symbol: &ResolvedSymbol,
+) -> serde_json::Value
+where
+    I: IntoIterator<Item = (&'a Url, &'a File)>,
+{
+    let mut files = rename_spans(files, current_uri, symbol);
+    files.sort_by(|(a, _, _), (b, _, _)| a.as_str().cmp(b.as_str()));
+    let mut entries = Vec::new();
+    for (uri, file, spans) in files {
+        let text = file.source.text();
+        for span in spans {
+            let line_start = text[..span.start].rfind('\n').map_or(0, |at| at + 1);
+            let line_end = text[span.start..]
+                .find('\n')
+                .map_or(text.len(), |at| span.start + at);
+            let range = Range::new(
+                file.source.position_at(span.start),
+                file.source.position_at(span.end),
+            );
+            entries.push(serde_json::json!({
+                "uri": uri,
+                "range": range,
+                "line": text[line_start..line_end].trim_end_matches('\r'),
+            }));
         }
     }
+    serde_json::Value::Array(entries)
+}
 
-    changes.retain(|_, edits| !edits.is_empty());
-    changes
+/// The spans a rename of `symbol` rewrites, for each file that has any.
+/// Locals and type variables are only looked for in the current file.
+fn rename_spans<'a, I>(
+    files: I,
+    current_uri: &Url,
+    symbol: &ResolvedSymbol,
+) -> Vec<(&'a Url, &'a File, Vec<Span>)>
+where
+    I: IntoIterator<Item = (&'a Url, &'a File)>,
+{
+    let local_only = symbol.target_span.is_some() || symbol.kind == SymbolOccurrenceKind::TypeVar;
+    files
+        .into_iter()
+        .filter(|(uri, _)| !local_only || *uri == current_uri)
+        .filter_map(|(uri, file)| {
+            let spans = symbol_spans_for_file(file, symbol, true)
+                .into_iter()
+                .map(|(span, _)| span)
+                .collect::<Vec<_>>();
+            (!spans.is_empty()).then_some((uri, file, spans))
+        })
+        .collect()
+}
+
+/// Package rename edits for the client. With `versions` (the client supports
+/// `documentChanges`), each document's edit carries the version of its open
+/// buffer, or `null` for files that are not open, so a client can refuse
+/// edits computed against stale text. Otherwise fall back to `changes`.
+pub(crate) fn rename_workspace_edit(
+    changes: HashMap<Url, Vec<TextEdit>>,
+    versions: Option<&HashMap<Url, i32>>,
+) -> WorkspaceEdit {
+    let Some(versions) = versions else {
+        return WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        };
+    };
+
+    let mut changes = changes.into_iter().collect::<Vec<_>>();
+    changes.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+    let edits = changes
+        .into_iter()
+        .map(|(uri, edits)| TextDocumentEdit {
+            text_document: OptionalVersionedTextDocumentIdentifier {
+                version: versions.get(&uri).copied(),
+                uri,
+            },
+            edits: edits.into_iter().map(OneOf::Left).collect(),
+        })
+        .collect();
+    WorkspaceEdit {
+        changes: None,
+        document_changes: Some(DocumentChanges::Edits(edits)),
+        change_annotations: None,
+    }
 }
 
 fn is_identifier_byte(byte: u8) -> bool {
@@ -9,6 +9,39 @@ fn diagnostic_code_str(diagnostic: &tower_lsp::lsp_types::Diagnostic) -> Option<
     }
 }
 
+/// A fresh `sail-<name>-<pid>` directory under the system temp dir, removed
+/// when dropped so a failing assertion does not leave it behind.
+struct TempDir(std::path::PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("sail-{name}-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl std::ops::Deref for TempDir {
+    type Target = std::path::Path;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AsRef<std::path::Path> for TempDir {
+    fn as_ref(&self) -> &std::path::Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
 #[test]
 fn finds_call_and_argument_index() {
     let source = r#"
@@ -1014,6 +1047,57 @@ fn builds_selection_range_chain() {
     assert!(selection.parent.is_some());
 }
 
+#[test]
+fn pairs_nested_conditional_directives() {
+    let source = "$ifdef A\nlet x = 1\n$ifndef B\nlet y = 2\n$else\nlet y = 3\n$endif\n$endif\n$ifdef C\nlet z = 4\n";
+    let file = File::new(source.to_string());
+    let tokens = file.tokens.as_deref().unwrap();
+    let line_of = |offset: usize| file.source.position_at(offset).line;
+    let blocks: Vec<_> = conditional_directive_blocks(tokens)
+        .into_iter()
+        .map(|(open, close)| (line_of(open.start), line_of(close.start)))
+        .collect();
+    // The unterminated `$ifdef C` is skipped rather than paired.
+    assert_eq!(blocks, vec![(0, 7), (2, 6)]);
+
+    let inner = tower_lsp::lsp_types::Position::new(3, 4);
+    let mut selection = Some(Box::new(make_selection_range(&file, inner)));
+    let mut lines = Vec::new();
+    while let Some(range) = selection {
+        lines.push((range.range.start.line, range.range.end.line));
+        selection = range.parent;
+    }
+    assert!(lines.contains(&(2, 6)));
+    assert!(lines.contains(&(0, 7)));
+}
+
+#[test]
+fn collects_option_directive_flags() {
+    let source = "$option -dno_cast\nlet x = 1\n$option --strict-var  \n$include <prelude.sail>\n";
+    let file = File::new(source.to_string());
+    let tokens = file.tokens.as_deref().unwrap();
+    assert_eq!(
+        option_directive_flags(tokens),
+        vec!["-dno_cast".to_string(), "--strict-var".to_string()]
+    );
+}
+
+#[test]
+fn matching_brace_jumps_between_ifdef_and_endif() {
+    let source = "$ifdef A\nlet x = 1\n$endif\n";
+    let file = File::new(source.to_string());
+    let open = tower_lsp::lsp_types::Position::new(0, 1);
+    let close = tower_lsp::lsp_types::Position::new(2, 1);
+    assert_eq!(
+        matching_brace_position(&file, open),
+        Some(tower_lsp::lsp_types::Position::new(2, 0))
+    );
+    assert_eq!(
+        matching_brace_position(&file, close),
+        Some(tower_lsp::lsp_types::Position::new(0, 0))
+    );
+}
+
 #[test]
 fn builds_call_edges_for_file() {
     use crate::symbols::call_edges_to;
@@ -1035,6 +1119,7 @@ fn parses_named_type() {
         parse_named_type("option(my_type)"),
         Some("option".to_string())
     );
+    assert_eq!(parse_named_type("Bits(32)"), Some("Bits".to_string()));
 }
 
 #[test]
@@ -1104,6 +1189,107 @@ fn finds_implementation_locations() {
     assert!(!locations.is_empty());
 }
 
+#[test]
+fn field_access_resolves_to_struct_fields() {
+    let source = r#"struct point = { x : int, y : int }
+function y() -> int = 0
+function f(p : point) -> int = p.y + y()
+"#;
+    let file = File::new(source.to_string());
+    let uri = Url::parse("file:///tmp/test.sail").unwrap();
+    let files = || std::iter::once((&uri, &file));
+    let span_of = |needle: &str| {
+        let start = source.find(needle).unwrap() + needle.len() - 1;
+        sail_parser::Span::new(start, start + 1)
+    };
+
+    let fields = field_access_definitions(files(), &file, span_of("p.y")).unwrap();
+    assert_eq!(fields.len(), 1);
+    assert_eq!(
+        file.source.offset_at(&fields[0].range.start),
+        source.find("y :").unwrap()
+    );
+    assert!(field_access_definitions(files(), &file, span_of("+ y")).is_none());
+}
+
+#[test]
+fn operator_lookup_only_matches_operator_uses() {
+    let source = r#"val add_int : (int, int) -> int
+overload operator + = {add_int}
+function f(a, b) = a+b // a+b
+let s = "a+b"
+"#;
+    let file = File::new(source.to_string());
+    let uri = Url::parse("file:///tmp/test.sail").unwrap();
+    let files = || std::iter::once((&uri, &file));
+    let plus = source.find("a+b").unwrap() + 1;
+
+    let (span, decls) = operator_decls_at(files(), &file, plus).expect("operator");
+    assert_eq!(span, sail_parser::Span::new(plus, plus + 1));
+    assert_eq!(decls.len(), 1);
+    assert_eq!(decls[0].2.members, ["add_int"]);
+
+    // The identifier right after the operator is not part of it.
+    assert!(operator_decls_at(files(), &file, plus + 1).is_none());
+    let in_comment = source.find("// a+b").unwrap() + "// a".len();
+    assert!(operator_decls_at(files(), &file, in_comment).is_none());
+    let in_string = source.find("\"a+b").unwrap() + "\"a".len();
+    assert!(operator_decls_at(files(), &file, in_string).is_none());
+}
+
+#[test]
+fn links_outcomes_to_their_impls() {
+    let source = r#"outcome sail_barrier : barrier_kind -> unit
+impl sail_barrier(_) = ()
+function f() -> unit = sail_barrier(Barrier_DMB)
+"#;
+    let file = File::new(source.to_string());
+    let uri = Url::parse("file:///tmp/test.sail").unwrap();
+    let files = || std::iter::once((&uri, &file));
+    let start =
+        |location: &tower_lsp::lsp_types::Location| file.source.offset_at(&location.range.start);
+
+    let impls = implementation_locations(files(), &uri, "sail_barrier");
+    assert_eq!(impls.len(), 1);
+    assert_eq!(start(&impls[0]), source.find("sail_barrier(_)").unwrap());
+
+    let definitions = symbol_definition_locations(files(), &uri, "sail_barrier");
+    assert_eq!(definitions.len(), 1);
+    assert_eq!(
+        start(&definitions[0]),
+        source.find("sail_barrier :").unwrap()
+    );
+}
+
+#[test]
+fn finds_scattered_type_clauses_as_implementations() {
+    let source = r#"scattered union ast
+union clause ast = ADD : bits(5)
+union clause ast = SUB : bits(5)
+end ast
+register PC : bits(64)
+"#;
+    let file = File::new(source.to_string());
+    let uri = Url::parse("file:///tmp/test.sail").unwrap();
+    let files = || std::iter::once((&uri, &file));
+
+    let clauses = implementation_locations(files(), &uri, "ast")
+        .iter()
+        .map(|location| file.source.offset_at(&location.range.start))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        clauses,
+        [source.find("ADD").unwrap(), source.find("SUB").unwrap()]
+    );
+
+    let register = implementation_locations(files(), &uri, "PC");
+    assert_eq!(register.len(), 1);
+    assert_eq!(
+        file.source.offset_at(&register[0].range.start),
+        source.find("PC").unwrap()
+    );
+}
+
 #[test]
 fn formats_document_indentation() {
     let options = FormattingOptions {
@@ -1151,6 +1337,37 @@ fn formats_only_selected_range() {
     assert_eq!(edits[0].new_text, "  let x = [1,\n    2]\n");
 }
 
+#[test]
+fn formats_workspace_files_without_syntax_errors() {
+    let options = FormattingOptions {
+        tab_size: 2,
+        insert_spaces: true,
+        properties: HashMap::new(),
+        trim_trailing_whitespace: Some(true),
+        insert_final_newline: None,
+        trim_final_newlines: None,
+    };
+    let messy_uri = Url::parse("file:///tmp/messy.sail").unwrap();
+    let messy = File::new("function f() = {\nlet x = 1;\nx\n}\n".to_string());
+    let clean_uri = Url::parse("file:///tmp/clean.sail").unwrap();
+    let clean = File::new("function g() = 1\n".to_string());
+    let broken_uri = Url::parse("file:///tmp/broken.sail").unwrap();
+    let broken = File::new("function h() = {\nlet y = ;\n".to_string());
+    let files = [
+        (&messy_uri, &messy),
+        (&clean_uri, &clean),
+        (&broken_uri, &broken),
+    ];
+
+    let (changes, skipped) = format_workspace_edits(files, &options);
+    assert_eq!(changes.keys().collect::<Vec<_>>(), [&messy_uri]);
+    assert_eq!(
+        changes[&messy_uri][0].new_text,
+        "function f() = {\n  let x = 1;\n  x\n}\n"
+    );
+    assert_eq!(skipped, [broken_uri]);
+}
+
 #[test]
 fn preserves_existing_continuation_indent() {
     let options = FormattingOptions {
@@ -1206,6 +1423,27 @@ fn returns_linked_editing_ranges_for_identifier() {
     assert!(linked.ranges.len() >= 2);
 }
 
+#[test]
+fn linked_editing_ranges_match_whole_identifiers_only() {
+    let source = "let x = xs + x\n";
+    let file = File::new(source.to_string());
+    let position = file.source.position_at(source.find('x').expect("x"));
+    let linked = linked_editing_ranges_for_position(&file, position).expect("linked ranges");
+    let texts: Vec<_> = linked
+        .ranges
+        .iter()
+        .map(|range| {
+            let start = file.source.offset_at(&range.start);
+            let end = file.source.offset_at(&range.end);
+            &source[start..end]
+        })
+        .collect();
+    assert_eq!(texts, vec!["x", "x"]);
+
+    let on_operator = file.source.position_at(source.find('+').expect("plus"));
+    assert!(linked_editing_ranges_for_position(&file, on_operator).is_none());
+}
+
 #[test]
 fn extracts_document_links() {
     let uri = Url::parse("file:///tmp/main.sail").unwrap();
@@ -1221,16 +1459,55 @@ fn extracts_document_links() {
     }));
 }
 
+#[test]
+fn links_include_directives() {
+    let uri = Url::parse("file:///tmp/model/main.sail").unwrap();
+    let source = "$include \"defs/regs.sail\"\n$include <prelude.sail>\n";
+    let file = File::new(source.to_string());
+    let links = document_links_for_file(&uri, &file);
+    let link = links
+        .iter()
+        .find(|link| link.tooltip.as_deref() == Some("Open included file"))
+        .expect("include link");
+    assert_eq!(
+        link.target.as_ref().unwrap().as_str(),
+        "file:///tmp/model/defs/regs.sail"
+    );
+    assert_eq!(link.range.start, tower_lsp::lsp_types::Position::new(0, 9));
+    assert_eq!(link.range.end, tower_lsp::lsp_types::Position::new(0, 25));
+
+    let (target, range) = include_link_target(
+        &uri,
+        " <prelude.sail>",
+        Some(std::path::Path::new("/opt/sail/lib")),
+    )
+    .expect("library include");
+    assert_eq!(target.as_str(), "file:///opt/sail/lib/prelude.sail");
+    assert_eq!(range, 1..15);
+    assert!(include_link_target(&uri, " <prelude.sail>", None).is_none());
+
+    let (target, _) =
+        include_link_target(&uri, " \"../common/./defs.sail\"", None).expect("relative include");
+    assert_eq!(target.as_str(), "file:///tmp/common/defs.sail");
+}
+
 #[test]
 fn builds_code_lenses_for_declarations() {
     let source = "val foo : int\nfunction foo() = 1\n";
     let file = File::new(source.to_string());
     let uri = Url::parse("file:///tmp/main.sail").unwrap();
     let all_files = vec![(&uri, &file)];
-    let refs = collect_reference_counts(&all_files);
-    let impls = collect_implementation_counts(&all_files);
-    let lenses = code_lenses_for_file(&file, &refs, &impls);
+    let lenses = code_lenses_for_file(&file);
     assert!(lenses.len() >= 3);
+    assert!(lenses
+        .iter()
+        .filter_map(|lens| lens.data.as_ref())
+        .all(|data| data.get("count").is_none()));
+    let titles = lenses
+        .iter()
+        .filter_map(|lens| code_lens_title(lens.data.as_ref()?, &all_files))
+        .collect::<Vec<_>>();
+    assert!(titles.contains(&"1 implementation".to_string()));
     assert!(
         lenses
             .iter()
@@ -1239,12 +1516,94 @@ fn builds_code_lenses_for_declarations() {
     );
 }
 
+#[test]
+fn document_symbols_cover_bodies_and_nest_members() {
+    let source = r#"enum color = { Red, Green }
+struct point = {
+  x : int,
+  y : int,
+}
+function f() = {
+  let a = 1;
+  a
+}
+"#;
+    let file = File::new(source.to_string());
+    let tree = document_symbol_tree(&file);
+
+    let color = tree.iter().find(|sym| sym.name == "color").unwrap();
+    let members = color.children.as_ref().unwrap();
+    assert_eq!(
+        members
+            .iter()
+            .map(|sym| sym.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["Red", "Green"]
+    );
+    assert!(members
+        .iter()
+        .all(|sym| sym.kind == SymbolKind::ENUM_MEMBER));
+
+    let point = tree.iter().find(|sym| sym.name == "point").unwrap();
+    let fields = point.children.as_ref().unwrap();
+    assert_eq!(
+        fields
+            .iter()
+            .map(|sym| sym.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["x", "y"]
+    );
+    assert!(fields.iter().all(|sym| sym.kind == SymbolKind::FIELD));
+    assert_eq!(point.range.start.line, 1);
+    assert_eq!(point.range.end.line, 4);
+
+    let f = tree.iter().find(|sym| sym.name == "f").unwrap();
+    assert_eq!(f.selection_range.start.line, 5);
+    assert_eq!(f.selection_range.end.line, 5);
+    assert_eq!(f.range.start.line, 5);
+    assert_eq!(f.range.end.line, 8);
+    assert!(tree.iter().all(|sym| sym.name != "a" && sym.name != "Red"));
+}
+
+#[test]
+fn document_symbols_parent_scattered_clauses_under_their_head() {
+    let source = r#"scattered union ast
+union clause ast = ADD : int
+union clause ast = SUB : int
+end ast
+enum clause extra = Foo
+"#;
+    let file = File::new(source.to_string());
+    let tree = document_symbol_tree(&file);
+
+    let ast = tree.iter().find(|sym| sym.name == "ast").unwrap();
+    let variants = ast.children.as_ref().unwrap();
+    assert_eq!(
+        variants
+            .iter()
+            .map(|sym| sym.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["ADD", "SUB"]
+    );
+    // The head of `extra` lives elsewhere, so its member stays at the root.
+    assert!(tree.iter().any(|sym| sym.name == "Foo"));
+}
+
 #[test]
 fn builds_code_lens_title_from_data() {
-    let refs = serde_json::json!({"kind":"refs","count":2});
-    let impls = serde_json::json!({"kind":"impls","count":1});
-    assert_eq!(code_lens_title(&refs).as_deref(), Some("2 references"));
-    assert_eq!(code_lens_title(&impls).as_deref(), Some("1 implementation"));
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let file = File::new("function foo() = 1\nfunction bar() = foo() + foo()\n".to_string());
+    let all_files = vec![(&uri, &file)];
+    let refs = serde_json::json!({"kind":"refs","name":"foo"});
+    let impls = serde_json::json!({"kind":"impls","name":"foo"});
+    assert_eq!(
+        code_lens_title(&refs, &all_files).as_deref(),
+        Some("2 references")
+    );
+    assert_eq!(
+        code_lens_title(&impls, &all_files).as_deref(),
+        Some("1 implementation")
+    );
 }
 
 #[test]
@@ -1449,6 +1808,93 @@ function clause foo(x) = x + 1
     assert_eq!(locations.len(), 2);
 }
 
+#[test]
+fn finds_function_definitions_in_every_syntax() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let cases = [
+        ("function foo(x) = x + 1\n", (0, 9)),
+        ("function foo x = match x { 0 => 1, _ => 2 }\n", (0, 9)),
+        ("$[complete]\nfunction foo(x) = x\n", (1, 9)),
+        ("$[complete] function foo(x) = x\n", (0, 21)),
+        ("private function foo(x) = x\n", (0, 17)),
+        (
+            "function foo forall 'n. (x : bits('n)) -> int = 0\n",
+            (0, 9),
+        ),
+        (
+            "scattered function foo\nfunction clause foo(x) = x\nend foo\n",
+            (1, 16),
+        ),
+    ];
+    for (source, (line, character)) in cases {
+        let file = File::new(source.to_string());
+        let locations = symbol_definition_locations(std::iter::once((&uri, &file)), &uri, "foo");
+        let starts = locations
+            .iter()
+            .map(|location| location.range.start)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            starts,
+            vec![tower_lsp::lsp_types::Position::new(line, character)],
+            "{source}"
+        );
+    }
+}
+
+#[test]
+fn document_symbol_details_show_declared_types() {
+    let source = r#"val add : (int,
+  int) -> int
+val eq_int = { ocaml: "eq_int", c: "eq_int" } : (int, int) -> bool
+function add(x, y) = x + y
+function twice(x : int) -> int = x * 2
+function untyped() = ()
+register PC : bits(64) = zeros()
+let xlen : int = 64
+let width = 8
+"#;
+    let file = File::new(source.to_string());
+    let tree = document_symbol_tree(&file);
+    let details = tree
+        .iter()
+        .map(|sym| (sym.name.as_str(), sym.detail.as_deref().unwrap_or_default()))
+        .collect::<Vec<_>>();
+
+    assert!(details.contains(&("add", "(int, int) -> int")));
+    assert!(details.contains(&("eq_int", "(int, int) -> bool")));
+    assert!(details.contains(&("twice", "(x : int) -> int")));
+    assert!(details.contains(&("untyped", "function")));
+    assert!(details.contains(&("PC", "bits(64)")));
+    assert!(details.contains(&("xlen", "int")));
+    assert!(details.contains(&("width", "binding")));
+}
+
+#[test]
+fn scattered_mapping_definition_starts_at_its_head() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = r#"
+scattered mapping enc
+mapping clause enc = 0 <-> 0b0
+mapping clause enc = 1 <-> 0b1
+"#;
+    let file = File::new(source.to_string());
+    let locations = symbol_definition_locations(std::iter::once((&uri, &file)), &uri, "enc");
+
+    assert_eq!(locations.len(), 3);
+    assert_eq!(
+        locations[0].range.start,
+        tower_lsp::lsp_types::Position::new(1, 18)
+    );
+
+    let tree = document_symbol_tree(&file);
+    assert_eq!(tree.len(), 1);
+    let clauses = tree[0].children.as_ref().unwrap();
+    assert_eq!(clauses.len(), 2);
+    assert!(clauses
+        .iter()
+        .all(|sym| sym.detail.as_deref() == Some("mapping clause")));
+}
+
 #[test]
 fn finds_symbol_declaration_locations_for_scattered_head() {
     let uri = Url::parse("file:///tmp/main.sail").unwrap();
@@ -1471,9 +1917,12 @@ function clause foo(x) = x + 1
 "#;
     let file = File::new(source.to_string());
     let all_files = vec![(&uri, &file)];
-    let impls = collect_implementation_counts(&all_files);
+    let impls = serde_json::json!({"kind":"impls","name":"foo"});
 
-    assert_eq!(impls.get("foo").copied(), Some(2));
+    assert_eq!(
+        code_lens_title(&impls, &all_files).as_deref(),
+        Some("2 implementations")
+    );
 }
 
 #[test]
@@ -1510,6 +1959,43 @@ fn computes_type_hierarchy_relations() {
     assert!(subs.iter().any(|item| item.name == "grandchild"));
 }
 
+#[test]
+fn type_hierarchy_follows_embedded_record_types() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "enum mode = {M, S, U}\nstruct csr = {priv : mode, bits : bits(64)}\nunion ast = {\n  CSR : (csr, mode),\n  NOP : unit\n}\n";
+    let file = File::new(source.to_string());
+
+    let supers = type_supertypes(std::iter::once((&uri, &file)), &uri, "csr");
+    assert_eq!(
+        supers
+            .iter()
+            .map(|item| item.name.as_str())
+            .collect::<Vec<_>>(),
+        vec!["mode"]
+    );
+
+    let mut subs: Vec<_> = type_subtypes(std::iter::once((&uri, &file)), &uri, "mode")
+        .into_iter()
+        .map(|item| item.name)
+        .collect();
+    subs.sort();
+    assert_eq!(subs, vec!["ast".to_string(), "csr".to_string()]);
+}
+
+#[test]
+fn type_variable_definition_is_its_forall_binder() {
+    use crate::symbols::type_var_definition_location;
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "val pad : forall 'n. bits('n) -> bits('n)\n";
+    let file = File::new(source.to_string());
+    let use_pos = file.source.position_at(source.rfind("'n").unwrap());
+    let location = type_var_definition_location(&uri, &file, use_pos).expect("binder");
+    assert_eq!(
+        location.range.start,
+        file.source.position_at(source.find("'n").unwrap())
+    );
+}
+
 #[test]
 fn finds_type_candidates_at_position() {
     let source = "let x : child = y\n";
@@ -1522,9 +2008,10 @@ fn finds_type_candidates_at_position() {
 
 #[test]
 fn builds_document_diagnostic_report_and_unchanged() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
     let file = File::new("let x =\n".to_string());
     assert!(file.parsed().is_some());
-    let full = document_diagnostic_report_for_file(&file, None);
+    let full = document_diagnostic_report_for_file(&uri, &file, None, &[], None, None, true);
     let result_id = match full {
         DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(report)) => report
             .full_document_diagnostic_report
@@ -1532,21 +2019,56 @@ fn builds_document_diagnostic_report_and_unchanged() {
             .expect("result id"),
         _ => panic!("expected full report"),
     };
-    let unchanged = document_diagnostic_report_for_file(&file, Some(&result_id));
+    let unchanged =
+        document_diagnostic_report_for_file(&uri, &file, None, &[], None, Some(&result_id), true);
     assert!(matches!(
         unchanged,
         DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Unchanged(_))
     ));
 }
 
+#[test]
+fn flags_definitions_repeated_in_other_files() {
+    use crate::diagnostics::DefinitionIndex;
+    let a_uri = Url::parse("file:///tmp/a.sail").unwrap();
+    let b_uri = Url::parse("file:///tmp/b.sail").unwrap();
+    let a = File::new(
+        "type word = bits(32)\nfunction f() = 1\nscattered union ast\noverload op = {f}\n"
+            .to_string(),
+    );
+    let b = File::new(
+        "let word = 0\nfunction f() = 2\nunion clause ast = G : unit\noverload op = {f}\n"
+            .to_string(),
+    );
+    let index = DefinitionIndex::new([(&a_uri, &a), (&b_uri, &b)]);
+
+    let diagnostics = index.duplicate_diagnostics(&a_uri, &a);
+    assert_eq!(diagnostics.len(), 1, "{diagnostics:?}");
+    assert_eq!(diagnostics[0].message, "`f` is also defined in b.sail");
+    assert_eq!(
+        diagnostic_code_str(&diagnostics[0]),
+        Some("duplicate-definition")
+    );
+    let related = diagnostics[0].related_information.as_ref().unwrap();
+    assert_eq!(related.len(), 1);
+    assert_eq!(related[0].location.uri, b_uri);
+    assert_eq!(related[0].location.range.start.line, 1);
+}
+
 #[test]
 fn builds_workspace_diagnostic_report() {
     let uri = Url::parse("file:///tmp/main.sail").unwrap();
     let file = File::new("let x =\n".to_string());
     let mut versions = HashMap::new();
     versions.insert(uri.clone(), 3);
-    let report =
-        workspace_diagnostic_report(std::iter::once((&uri, &file)), &versions, &HashMap::new());
+    let report = workspace_diagnostic_report(
+        std::iter::once((&uri, &file)),
+        None,
+        &[],
+        &versions,
+        &HashMap::new(),
+        |_| true,
+    );
     match report {
         WorkspaceDiagnosticReportResult::Report(report) => {
             assert_eq!(report.items.len(), 1);
@@ -1555,6 +2077,38 @@ fn builds_workspace_diagnostic_report() {
     }
 }
 
+#[test]
+fn workspace_diagnostic_report_clears_files_out_of_scope() {
+    let project_uri = Url::parse("file:///work/main.sail").unwrap();
+    let library_uri = Url::parse("file:///opt/sail/lib/prelude.sail").unwrap();
+    let file = File::new("function f() = {\n".to_string());
+    let report = workspace_diagnostic_report(
+        [(&project_uri, &file), (&library_uri, &file)],
+        None,
+        &[],
+        &HashMap::new(),
+        &HashMap::new(),
+        |uri| uri.as_str().starts_with("file:///work/"),
+    );
+    let WorkspaceDiagnosticReportResult::Report(report) = report else {
+        panic!("expected full workspace report");
+    };
+    let counts = report
+        .items
+        .iter()
+        .map(|item| match item {
+            tower_lsp::lsp_types::WorkspaceDocumentDiagnosticReport::Full(full) => (
+                full.uri.as_str(),
+                full.full_document_diagnostic_report.items.len(),
+            ),
+            _ => panic!("expected full reports"),
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(counts.len(), 2);
+    assert!(counts[0].1 > 0);
+    assert_eq!(counts[1], (library_uri.as_str(), 0));
+}
+
 #[test]
 fn creates_will_rename_file_edits() {
     let uri = Url::parse("file:///tmp/main.sail").unwrap();
@@ -1681,48 +2235,751 @@ fn top_level_references_ignore_shadowed_local_bindings() {
 }
 
 #[test]
-fn renames_type_variables_within_their_own_scope_only() {
-    let uri1 = Url::parse("file:///tmp/a.sail").unwrap();
-    let uri2 = Url::parse("file:///tmp/b.sail").unwrap();
-    let source1 = "val f : forall ('n). bits('n) -> bits('n)\n";
-    let source2 = "val g : forall ('n). bits('n) -> bits('n)\n";
-    let file1 = File::new(source1.to_string());
-    let file2 = File::new(source2.to_string());
-    assert!(file1.core_ast().is_some());
-    assert!(file2.core_ast().is_some());
-    let pos = file1
-        .source
-        .position_at(source1.find("'n").expect("type var"));
-
-    let symbol = resolve_symbol_at(&file1, pos).expect("resolved symbol");
-    let changes = rename_edits(vec![(&uri1, &file1), (&uri2, &file2)], &uri1, &symbol, "'m");
+fn references_leave_out_declarations_on_request() {
+    let uri = Url::parse("file:///tmp/a.sail").unwrap();
+    let source = "val foo : unit -> int\nfunction foo() = 1\nfunction use_foo() = foo()\n";
+    let file = File::new(source.to_string());
+    let pos = file.source.position_at(source.rfind("foo()").unwrap());
+    let symbol = resolve_symbol_at(&file, pos).expect("resolved symbol");
+    let lines = |include_declaration: bool| {
+        reference_locations(vec![(&uri, &file)], &uri, &symbol, include_declaration)
+            .iter()
+            .map(|location| location.range.start.line)
+            .collect::<Vec<_>>()
+    };
 
-    assert_eq!(changes.len(), 1);
-    assert_eq!(changes.get(&uri1).map(Vec::len), Some(3));
-    assert!(!changes.contains_key(&uri2));
+    assert_eq!(lines(true), [0, 1, 2]);
+    assert_eq!(lines(false), [2]);
 }
 
 #[test]
-fn completion_uses_ast_scoped_bindings_for_local_candidates() {
-    let uri = Url::parse("file:///tmp/main.sail").unwrap();
-    let source = "function foo() = {\n  let local_value = 1;\n  local_\n}\n";
+fn references_and_renames_are_case_sensitive() {
+    let uri = Url::parse("file:///tmp/a.sail").unwrap();
+    let source = "function Foo() = 1\nfunction foo() = 2\nlet x = Foo() + foo()\n";
     let file = File::new(source.to_string());
-    let offset = source.find("local_\n").expect("completion site") + "local_".len();
-    let prefix = completion_prefix(file.source.text(), offset);
-    let items = build_completion_items(
-        std::iter::once((&uri, &file)),
-        &uri,
-        file.source.text(),
-        offset,
-        prefix,
-        SAIL_KEYWORDS,
-        SAIL_BUILTINS,
-    );
+    let pos = file.source.position_at(source.find("Foo").unwrap());
+    let symbol = resolve_symbol_at(&file, pos).expect("resolved symbol");
 
-    assert!(items.iter().any(|item| item.label == "local_value"));
-}
+    let references = reference_locations(vec![(&uri, &file)], &uri, &symbol, true);
+    assert_eq!(references.len(), 2);
 
-#[test]
+    let changes = rename_edits(vec![(&uri, &file)], &uri, &symbol, "Bar");
+    let renamed = changes[&uri]
+        .iter()
+        .map(|edit| file.source.offset_at(&edit.range.start))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        renamed,
+        [source.find("Foo").unwrap(), source.rfind("Foo").unwrap()]
+    );
+}
+
+#[test]
+fn references_after_multibyte_text_end_on_char_boundaries() {
+    let uri = Url::parse("file:///tmp/a.sail").unwrap();
+    // Non-ASCII text ahead of the name on the same line, and the name as the
+    // last thing on its line.
+    let source = "let n = 1 // größe\nfunction f() = \"ü✓\" ^ n\n";
+    let file = File::new(source.to_string());
+    let pos = file.source.position_at(source.find('n').unwrap());
+    let symbol = resolve_symbol_at(&file, pos).expect("resolved symbol");
+
+    let references = reference_locations(vec![(&uri, &file)], &uri, &symbol, true);
+    let last_line = source.lines().nth(1).unwrap();
+    let column = last_line[..last_line.len() - 1].encode_utf16().count() as u32;
+    assert_eq!(references.len(), 2);
+    assert_eq!(
+        references[1].range,
+        Range::new(
+            tower_lsp::lsp_types::Position::new(1, column),
+            tower_lsp::lsp_types::Position::new(1, column + 1),
+        )
+    );
+
+    let preview = rename_preview(vec![(&uri, &file)], &uri, &symbol);
+    assert_eq!(preview[1]["line"], last_line);
+    let changes = rename_edits(vec![(&uri, &file)], &uri, &symbol, "count");
+    assert_eq!(changes[&uri].len(), 2);
+}
+
+#[test]
+fn previews_rename_occurrences_with_their_lines() {
+    let uri = Url::parse("file:///tmp/a.sail").unwrap();
+    let source = "function foo() = 1\nlet x = foo()\n";
+    let file = File::new(source.to_string());
+    let pos = file.source.position_at(source.find("foo").unwrap());
+    let symbol = resolve_symbol_at(&file, pos).expect("resolved symbol");
+
+    let preview = rename_preview(vec![(&uri, &file)], &uri, &symbol);
+    let lines = preview
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|entry| entry["line"].as_str().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(lines, ["function foo() = 1", "let x = foo()"]);
+    assert_eq!(preview[1]["range"]["start"]["character"], 8);
+    assert_eq!(preview[0]["uri"], "file:///tmp/a.sail");
+}
+
+#[test]
+fn renames_type_variables_within_their_own_scope_only() {
+    let uri1 = Url::parse("file:///tmp/a.sail").unwrap();
+    let uri2 = Url::parse("file:///tmp/b.sail").unwrap();
+    let source1 = "val f : forall ('n). bits('n) -> bits('n)\n";
+    let source2 = "val g : forall ('n). bits('n) -> bits('n)\n";
+    let file1 = File::new(source1.to_string());
+    let file2 = File::new(source2.to_string());
+    assert!(file1.core_ast().is_some());
+    assert!(file2.core_ast().is_some());
+    let pos = file1
+        .source
+        .position_at(source1.find("'n").expect("type var"));
+
+    let symbol = resolve_symbol_at(&file1, pos).expect("resolved symbol");
+    let changes = rename_edits(vec![(&uri1, &file1), (&uri2, &file2)], &uri1, &symbol, "'m");
+
+    assert_eq!(changes.len(), 1);
+    assert_eq!(changes.get(&uri1).map(Vec::len), Some(3));
+    assert!(!changes.contains_key(&uri2));
+}
+
+#[test]
+fn versions_rename_edits_when_client_supports_document_changes() {
+    let open = Url::parse("file:///tmp/a.sail").unwrap();
+    let closed = Url::parse("file:///tmp/b.sail").unwrap();
+    let edit = TextEdit {
+        range: Range::default(),
+        new_text: "g".to_string(),
+    };
+    let changes = HashMap::from([
+        (closed.clone(), vec![edit.clone()]),
+        (open.clone(), vec![edit.clone()]),
+    ]);
+    let versions = HashMap::from([(open.clone(), 7)]);
+
+    let legacy = rename_workspace_edit(changes.clone(), None);
+    assert_eq!(legacy.changes.as_ref(), Some(&changes));
+    assert!(legacy.document_changes.is_none());
+
+    let versioned = rename_workspace_edit(changes, Some(&versions));
+    assert!(versioned.changes.is_none());
+    let Some(tower_lsp::lsp_types::DocumentChanges::Edits(edits)) = versioned.document_changes
+    else {
+        panic!("expected document edits");
+    };
+    let documents = edits
+        .iter()
+        .map(|edit| (&edit.text_document.uri, edit.text_document.version))
+        .collect::<Vec<_>>();
+    assert_eq!(documents, [(&open, Some(7)), (&closed, None)]);
+}
+
+#[test]
+fn completion_ranks_symbols_before_keywords_and_preselects_exact_match() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "function letter() = 1\nfunction main() = {\n  le\n}\nfunction again() = letter\n";
+    let file = File::new(source.to_string());
+    let complete = |offset: usize| {
+        let prefix = completion_prefix(file.source.text(), offset);
+        build_completion_items(
+            [(&uri, &file)],
+            &uri,
+            file.source.text(),
+            offset,
+            prefix,
+            |_| false,
+        )
+    };
+
+    let offset = source.find("  le\n").expect("completion site") + "  le".len();
+    let items = complete(offset);
+    let position = |label: &str| items.iter().position(|item| item.label == label);
+    assert!(position("letter").expect("function") < position("let").expect("keyword"));
+    assert!(items.iter().all(|item| item.preselect.is_none()));
+
+    let offset = source.rfind("letter").expect("use") + "letter".len();
+    let items = complete(offset);
+    assert_eq!(items[0].label, "letter");
+    assert_eq!(items[0].preselect, Some(true));
+}
+
+#[test]
+fn completion_skips_the_name_being_defined() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "function format_word() = 1\nfunction form\n";
+    let file = File::new(source.to_string());
+    let offset = source.len() - 1;
+    let items = build_completion_items(
+        [(&uri, &file)],
+        &uri,
+        file.source.text(),
+        offset,
+        completion_prefix(file.source.text(), offset),
+        |_| false,
+    );
+    let labels = items
+        .iter()
+        .map(|item| item.label.as_str())
+        .collect::<Vec<_>>();
+    assert!(labels.contains(&"format_word"));
+    assert!(!labels.contains(&"form"));
+
+    let source = "function f() = {\n  let total = 1;\n  let tot\n}\n";
+    let file = File::new(source.to_string());
+    let offset = source.find("tot\n").expect("binding") + "tot".len();
+    let items = build_completion_items(
+        [(&uri, &file)],
+        &uri,
+        file.source.text(),
+        offset,
+        "tot",
+        |_| false,
+    );
+    let labels = items
+        .iter()
+        .map(|item| item.label.as_str())
+        .collect::<Vec<_>>();
+    assert!(labels.contains(&"total"));
+    assert!(!labels.contains(&"tot"));
+}
+
+#[test]
+fn completion_offers_type_level_constants_in_types() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "type xlen : Int = 64\nlet answer = 42\nfunction f(x : bits(8)) = answer\n";
+    let file = File::new(source.to_string());
+    let complete = |offset: usize| {
+        build_completion_items(
+            [(&uri, &file)],
+            &uri,
+            file.source.text(),
+            offset,
+            "",
+            |_| false,
+        )
+    };
+    let find = |items: &[tower_lsp::lsp_types::CompletionItem], label: &str| {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .map(|item| (item.kind.unwrap(), item.detail.clone().unwrap()))
+    };
+
+    let in_type = complete(source.find("bits(").unwrap() + "bits(".len());
+    assert_eq!(
+        find(&in_type, "xlen"),
+        Some((
+            tower_lsp::lsp_types::CompletionItemKind::CONSTANT,
+            "Int = 64".to_string()
+        ))
+    );
+    assert!(find(&in_type, "answer").is_none());
+    assert!(find(&in_type, "f").is_none());
+
+    let in_body = complete(source.find("= answer").unwrap() + 2);
+    assert_eq!(
+        find(&in_body, "answer"),
+        Some((
+            tower_lsp::lsp_types::CompletionItemKind::CONSTANT,
+            "= 42".to_string()
+        ))
+    );
+    assert!(find(&in_body, "f").is_some());
+}
+
+#[test]
+fn completion_offers_enum_members_and_fields_with_their_type() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "enum color = {Red, Green}\nstruct point = {px : int, py : int}\nfunction f(p : point) = p.px\n";
+    let file = File::new(source.to_string());
+    let complete = |offset: usize, prefix: &str| {
+        build_completion_items(
+            [(&uri, &file)],
+            &uri,
+            file.source.text(),
+            offset,
+            prefix,
+            |_| false,
+        )
+    };
+    let find = |items: &[tower_lsp::lsp_types::CompletionItem], label: &str| {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .map(|item| (item.kind.unwrap(), item.detail.clone().unwrap()))
+    };
+
+    let in_body = complete(source.find("= p.").unwrap() + 2, "");
+    assert_eq!(
+        find(&in_body, "Red"),
+        Some((
+            tower_lsp::lsp_types::CompletionItemKind::ENUM_MEMBER,
+            "color".to_string()
+        ))
+    );
+    assert!(find(&in_body, "px").is_none());
+
+    let after_dot = complete(source.rfind("px").unwrap() + 1, "p");
+    assert_eq!(
+        find(&after_dot, "px"),
+        Some((
+            tower_lsp::lsp_types::CompletionItemKind::FIELD,
+            "point".to_string()
+        ))
+    );
+    assert!(find(&after_dot, "Red").is_none());
+}
+
+#[test]
+fn detects_type_expression_context() {
+    let at_end = |text: &str| in_type_expression(text, text.len());
+    assert!(at_end("val f : "));
+    assert!(at_end("val f : int -> bits("));
+    assert!(at_end("function f(x : vector(2, "));
+    assert!(at_end("type word = "));
+    assert!(at_end("val f : forall 'n, 'n >= 0. bits("));
+    assert!(!at_end("let x : int = "));
+    assert!(!at_end("function f() = {\n  "));
+    assert!(!at_end("let xs = x :: "));
+    assert!(!at_end("match x { A => "));
+}
+
+#[test]
+fn completes_include_paths_next_to_the_file() {
+    let root = TempDir::new("include");
+    std::fs::create_dir_all(root.join("arch")).unwrap();
+    std::fs::write(root.join("regs.sail"), "").unwrap();
+    std::fs::write(root.join("arch/insts.sail"), "").unwrap();
+    std::fs::write(root.join("notes.txt"), "").unwrap();
+    let uri = Url::from_file_path(root.join("main.sail")).unwrap();
+    std::fs::write(root.join("main.sail"), "").unwrap();
+
+    // `|` marks the cursor.
+    let complete = |source: &str| {
+        let offset = source.find('|').unwrap();
+        let file = File::new(source.replace('|', ""));
+        include_path_completions(&uri, &file, offset, None).map(|items| {
+            items
+                .into_iter()
+                .map(|item| match item.text_edit {
+                    Some(tower_lsp::lsp_types::CompletionTextEdit::Edit(edit)) => {
+                        (item.label, edit.new_text)
+                    }
+                    _ => panic!("expected a text edit"),
+                })
+                .collect::<Vec<_>>()
+        })
+    };
+    let top = complete("$include \"|");
+    let nested = complete("$include \"arch/in|\"");
+    let library = complete("$include <|");
+    let outside = complete("let s = \"|");
+
+    assert_eq!(
+        top.unwrap(),
+        [
+            ("arch/".to_string(), "arch/".to_string()),
+            ("regs.sail".to_string(), "regs.sail\"".to_string()),
+        ]
+    );
+    assert_eq!(
+        nested.unwrap(),
+        [("insts.sail".to_string(), "insts.sail".to_string())]
+    );
+    assert!(library.is_none());
+    assert!(outside.is_none());
+}
+
+#[test]
+fn warns_about_missing_include_targets() {
+    use crate::diagnostics::unresolved_include_diagnostics;
+
+    let root = TempDir::new("missing-include");
+    std::fs::write(root.join("present.sail"), "").unwrap();
+    let uri = Url::from_file_path(root.join("main.sail")).unwrap();
+    let file = File::new(
+        "$include \"present.sail\"\n$include \"absent.sail\"\n$include <prelude.sail>\n"
+            .to_string(),
+    );
+
+    let diagnostics = unresolved_include_diagnostics(&uri, &file, None);
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].message,
+        "Included file \"absent.sail\" not found"
+    );
+    assert_eq!(
+        diagnostics[0].range,
+        Range::new(
+            tower_lsp::lsp_types::Position::new(1, 9),
+            tower_lsp::lsp_types::Position::new(1, 22)
+        )
+    );
+
+    assert_eq!(file.include_diagnostics(&uri, None), diagnostics);
+    std::fs::write(root.join("absent.sail"), "").unwrap();
+    assert!(unresolved_include_diagnostics(&uri, &file, None).is_empty());
+
+    // The file's cached result stands until watched files are created or
+    // deleted.
+    assert_eq!(file.include_diagnostics(&uri, None), diagnostics);
+    file.forget_include_checks();
+    assert!(file.include_diagnostics(&uri, None).is_empty());
+}
+
+#[test]
+fn notes_unformatted_documents_with_format_fix() {
+    use crate::actions::{default_code_action_format_options, format_document_fix};
+    use crate::diagnostics::unformatted_diagnostic;
+
+    let options = default_code_action_format_options();
+    let formatted = File::new("function f() = {\n  let x = 1;\n  x\n}\n".to_string());
+    assert!(unformatted_diagnostic(&formatted, &options).is_none());
+
+    let file = File::new("function f() = {\n  let x = 1;\nx\n}\n".to_string());
+    let diagnostic = unformatted_diagnostic(&file, &options).expect("unformatted note");
+    assert_eq!(diagnostic.message, "file is not formatted");
+    assert_eq!(
+        diagnostic.severity,
+        Some(tower_lsp::lsp_types::DiagnosticSeverity::INFORMATION)
+    );
+    assert_eq!(
+        diagnostic.range,
+        Range::new(
+            tower_lsp::lsp_types::Position::new(2, 0),
+            tower_lsp::lsp_types::Position::new(2, 1)
+        )
+    );
+    let (title, edit, _) = format_document_fix(&file, &diagnostic, &options).expect("format fix");
+    assert_eq!(title, "Format document");
+    assert_eq!(edit.new_text, formatted.source.text());
+
+    // Broken files are not formatted, so they are not flagged either.
+    let broken = File::new("function f() = {\nlet x =\n".to_string());
+    assert!(unformatted_diagnostic(&broken, &options).is_none());
+}
+
+#[tokio::test]
+async fn format_check_follows_client_formatting_options() {
+    use crate::backend::Backend;
+    use tower_lsp::lsp_types::{
+        DidOpenTextDocumentParams, DocumentFormattingParams, TextDocumentIdentifier,
+        TextDocumentItem, WorkDoneProgressParams,
+    };
+    use tower_lsp::LanguageServer;
+
+    let (service, _) = tower_lsp::LspService::new(Backend::new_with_client);
+    let backend = service.inner();
+    backend.state.write().await.config.format_check = true;
+    let uri = Url::parse("untitled:four.sail").unwrap();
+    // Formatted by an editor that indents by 4 and keeps trailing spaces.
+    let text = "function f() = {\n    let x = 1;  \n    x\n}\n";
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri.clone(), "sail".to_string(), 0, text.into()),
+        })
+        .await;
+    async fn notes(backend: &Backend, uri: &Url) -> Vec<Diagnostic> {
+        let state = backend.state.read().await;
+        state.open_file_diagnostics(uri, None).unwrap_or_default()
+    }
+    // Before the client has formatted anything the defaults apply.
+    assert_eq!(notes(backend, &uri).await.len(), 1);
+
+    let mut options = crate::actions::default_code_action_format_options();
+    options.tab_size = 4;
+    options.trim_trailing_whitespace = Some(false);
+    let edits = backend
+        .formatting(DocumentFormattingParams {
+            text_document: TextDocumentIdentifier::new(uri.clone()),
+            options,
+            work_done_progress_params: WorkDoneProgressParams::default(),
+        })
+        .await
+        .unwrap();
+    assert!(edits.unwrap_or_default().is_empty());
+    assert!(notes(backend, &uri).await.is_empty());
+}
+
+#[test]
+fn completion_skips_decimal_points() {
+    let at_end = |text: &str| in_numeric_literal(text, text.len());
+    assert!(at_end("let x = 3."));
+    assert!(at_end("let x = 3.14"));
+    assert!(at_end("let x = (10."));
+    assert!(!at_end("let x = r3."));
+    assert!(!at_end("let x = p.x"));
+    assert!(!at_end("let x = 3.e"));
+    assert!(!at_end("."));
+}
+
+#[test]
+fn completion_uses_ast_scoped_bindings_for_local_candidates() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "function foo() = {\n  let local_value = 1;\n  local_\n}\n";
+    let file = File::new(source.to_string());
+    let offset = source.find("local_\n").expect("completion site") + "local_".len();
+    let prefix = completion_prefix(file.source.text(), offset);
+    let items = build_completion_items(
+        std::iter::once((&uri, &file)),
+        &uri,
+        file.source.text(),
+        offset,
+        prefix,
+        |_| false,
+    );
+
+    assert!(items.iter().any(|item| item.label == "local_value"));
+}
+
+#[test]
+fn completion_omits_local_bindings_from_other_definitions() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let other_uri = Url::parse("file:///tmp/other.sail").unwrap();
+    let source =
+        "function foo() = {\n  let local_value = 1;\n  local_value\n}\nfunction bar() = local_\n";
+    let other =
+        File::new("function baz() = {\n  let local_other = 1;\n  local_other\n}\n".to_string());
+    let file = File::new(source.to_string());
+    let offset = source.find("local_\n").expect("completion site") + "local_".len();
+    let prefix = completion_prefix(file.source.text(), offset);
+    let items = build_completion_items(
+        [(&uri, &file), (&other_uri, &other)],
+        &uri,
+        file.source.text(),
+        offset,
+        prefix,
+        |_| false,
+    );
+
+    assert!(items.iter().all(|item| item.label != "local_value"));
+    assert!(items.iter().all(|item| item.label != "local_other"));
+}
+
+#[test]
+fn function_completions_insert_call_snippets() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "val add : (int, int) -> int\nfunction add(x, y) = x + y\nfunction main() = ad\n";
+    let file = File::new(source.to_string());
+    let offset = source.find("ad\n").expect("completion site") + "ad".len();
+    let prefix = completion_prefix(file.source.text(), offset);
+    let mut items = build_completion_items(
+        [(&uri, &file)],
+        &uri,
+        file.source.text(),
+        offset,
+        prefix,
+        |_| false,
+    );
+
+    let add = items
+        .iter()
+        .find(|item| item.label == "add")
+        .expect("add item");
+    assert_eq!(
+        add.insert_text.as_deref(),
+        Some("add(${1:arg1}, ${2:arg2})")
+    );
+    assert_eq!(
+        add.insert_text_format,
+        Some(tower_lsp::lsp_types::InsertTextFormat::SNIPPET)
+    );
+
+    plain_call_completions(&mut items);
+    let add = items
+        .iter()
+        .find(|item| item.label == "add")
+        .expect("add item");
+    assert_eq!(add.insert_text.as_deref(), Some("add"));
+    assert_eq!(
+        add.insert_text_format,
+        Some(tower_lsp::lsp_types::InsertTextFormat::PLAIN_TEXT)
+    );
+}
+
+#[test]
+fn library_only_completions_are_marked_prelude() {
+    let library = Url::parse("file:///opt/sail/lib/prelude.sail").unwrap();
+    let library_file = File::new(
+        "val zero_extend : bits(8) -> bits(16)\nval overridden : int -> int\n".to_string(),
+    );
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "val overridden : int -> int\nfunction main() = \n";
+    let file = File::new(source.to_string());
+    let files = [(&library, &library_file), (&uri, &file)];
+    let offset = source.len() - 1;
+    let mut items = build_completion_items(files, &uri, file.source.text(), offset, "", |uri| {
+        uri == &library
+    });
+
+    use tower_lsp::lsp_types::CompletionItem;
+    fn item<'a>(items: &'a [CompletionItem], label: &str) -> &'a CompletionItem {
+        items.iter().find(|item| item.label == label).unwrap()
+    }
+    fn marker<'a>(items: &'a [CompletionItem], label: &str) -> Option<&'a str> {
+        item(items, label)
+            .label_details
+            .as_ref()
+            .and_then(|details| details.description.as_deref())
+    }
+    assert_eq!(marker(&items, "zero_extend"), Some("prelude"));
+    assert_eq!(
+        item(&items, "zero_extend").detail.as_deref(),
+        Some("value specification")
+    );
+    assert_eq!(marker(&items, "overridden"), None);
+
+    // The marker stays when label details are filled in.
+    add_completion_label_details(&mut items, files);
+    assert_eq!(marker(&items, "zero_extend"), Some("prelude"));
+}
+
+#[test]
+fn completion_label_details_show_signature_and_file() {
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let other = Url::parse("file:///tmp/lib/arith.sail").unwrap();
+    let other_file = File::new(
+        "val add : (int, int) -> int\nfunction add(x, y) = x + y\nregister counter : int\n"
+            .to_string(),
+    );
+    let source = "function main() = \n";
+    let file = File::new(source.to_string());
+    let files = [(&uri, &file), (&other, &other_file)];
+    let offset = source.len() - 1;
+    let mut items = build_completion_items(files, &uri, file.source.text(), offset, "", |_| false);
+    add_completion_label_details(&mut items, files);
+
+    let label_details = |label: &str| {
+        items
+            .iter()
+            .find(|item| item.label == label)
+            .and_then(|item| item.label_details.clone())
+            .map(|details| (details.detail, details.description))
+    };
+    assert_eq!(
+        label_details("add"),
+        Some((
+            Some(" : (int, int) -> int".to_string()),
+            Some("arith.sail".to_string())
+        ))
+    );
+    assert_eq!(
+        label_details("counter"),
+        Some((None, Some("arith.sail".to_string())))
+    );
+    assert_eq!(label_details("if"), None);
+}
+
+#[test]
+fn completes_enum_members_in_match_arms() {
+    let types_uri = Url::parse("file:///tmp/types.sail").unwrap();
+    let types = File::new(
+        "enum color = { Red, Green, Blue }\nunion shape = { Circle : int, Square : int }\n"
+            .to_string(),
+    );
+    let uri = Url::parse("file:///tmp/main.sail").unwrap();
+    let source = "function f(c : color, s : shape) -> int = {\n  let n = match c {\n    Red => 1,\n    G\n  };\n  match s {  }\n}\n";
+    let file = File::new(source.to_string());
+    let files = [(&types_uri, &types), (&uri, &file)];
+
+    let offset = source.find("    G").expect("arm site") + "    G".len();
+    let prefix = completion_prefix(file.source.text(), offset);
+    let items = match_arm_completions(&files, &uri, &file, offset, prefix);
+    let labels = items
+        .iter()
+        .map(|item| item.label.as_str())
+        .collect::<Vec<_>>();
+    assert_eq!(labels, ["Red", "Green", "Blue"]);
+    assert!(items
+        .iter()
+        .all(|item| item.kind == Some(tower_lsp::lsp_types::CompletionItemKind::ENUM_MEMBER)));
+
+    let offset = source.find("match s { ").expect("union match") + "match s { ".len();
+    let items = match_arm_completions(&files, &uri, &file, offset, "");
+    assert_eq!(items[0].label, "Circle");
+    assert_eq!(items[0].insert_text.as_deref(), Some("Circle(_)"));
+
+    let offset = source.find("=> 1").expect("arm body") + "=> ".len();
+    assert!(match_arm_completions(&files, &uri, &file, offset, "").is_empty());
+}
+
+#[test]
+fn config_reads_sail_section_and_keeps_unset_values() {
+    let mut config = Config::default();
+    assert!(config.completion_call_parentheses);
+
+    config.update(&serde_json::json!({ "sail": { "completion": { "callParentheses": false } } }));
+    assert!(!config.completion_call_parentheses);
+
+    config.update(&serde_json::json!({ "unrelated": true }));
+    assert!(!config.completion_call_parentheses);
+
+    config.update(&serde_json::json!({ "completion": { "callParentheses": true } }));
+    assert!(config.completion_call_parentheses);
+
+    assert_eq!(config.diagnostics_scope, DiagnosticsScope::Workspace);
+    config.update(&serde_json::json!({ "diagnostics": { "scope": "open" } }));
+    assert_eq!(config.diagnostics_scope, DiagnosticsScope::Open);
+    config.update(&serde_json::json!({ "diagnostics": { "scope": "everywhere" } }));
+    assert_eq!(config.diagnostics_scope, DiagnosticsScope::Open);
+    assert!(!config.format_check);
+    config.update(&serde_json::json!({ "diagnostics": { "formatting": true } }));
+    assert!(config.format_check);
+}
+
+#[test]
+fn diagnostic_overrides_downgrade_and_ignore_by_message() {
+    let mut config = Config::default();
+    config.update(&serde_json::json!({ "diagnostics": { "overrides": [
+        { "message": "^Incomplete.*pattern", "severity": "information" },
+        { "message": "unused", "severity": "ignore" },
+        { "message": "bad rule", "severity": "fatal" },
+        { "message": "unclosed (group", "severity": "hint" },
+    ] } }));
+    assert_eq!(config.diagnostic_overrides.len(), 2);
+
+    let diagnostic = |message: &str| Diagnostic {
+        severity: Some(tower_lsp::lsp_types::DiagnosticSeverity::WARNING),
+        message: message.to_string(),
+        ..Diagnostic::default()
+    };
+    let kept = apply_overrides(
+        vec![
+            diagnostic("Incomplete match pattern"),
+            diagnostic("Variable x is unused"),
+            diagnostic("Pattern is incomplete"),
+        ],
+        &config.diagnostic_overrides,
+    );
+    let kept = kept
+        .iter()
+        .map(|d| (d.message.as_str(), d.severity.unwrap()))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kept,
+        [
+            (
+                "Incomplete match pattern",
+                tower_lsp::lsp_types::DiagnosticSeverity::INFORMATION
+            ),
+            (
+                "Pattern is incomplete",
+                tower_lsp::lsp_types::DiagnosticSeverity::WARNING
+            ),
+        ]
+    );
+
+    config.update(&serde_json::json!({ "diagnostics": { "overrides": [
+        { "message": r"^x\d+(y|z)$", "severity": "ignore" },
+    ] } }));
+    let rule = &config.diagnostic_overrides[0];
+    assert!(rule.matches("x12z"));
+    assert!(!rule.matches("ax12z"));
+    assert!(!rule.matches("xy"));
+}
+
+#[test]
 fn detects_unmodified_mutable_variable() {
     // var x is never assigned to after declaration => should warn
     let source = "function foo() -> int = {\n  var x : int = 1;\n  x\n}\n";
@@ -1747,6 +3004,20 @@ fn detects_unmodified_mutable_variable() {
         .contains(&tower_lsp::lsp_types::DiagnosticTag::UNNECESSARY));
 }
 
+#[test]
+fn places_diagnostics_on_tab_indented_lines() {
+    // Editors count a tab as one UTF-16 unit however wide it is displayed.
+    let source = "function foo() -> int = {\n\t\tvar x : int = 1;\n\tx\n}\n";
+    let file = File::new(source.to_string());
+    let diagnostics = file.lsp_diagnostics();
+    let diag = diagnostics
+        .iter()
+        .find(|d| d.message.contains("never modified"))
+        .expect("unmodified-mutable-variable diagnostic");
+    assert_eq!(diag.range.start, tower_lsp::lsp_types::Position::new(1, 6));
+    assert_eq!(file.source.text_range(&diag.range), "x");
+}
+
 #[test]
 fn no_unmodified_warning_when_var_is_assigned() {
     // var x is assigned to later => no warning
@@ -2066,4 +3337,622 @@ function f() -> int = {
     );
 }
 
+#[test]
+fn workspace_scan_loads_every_sail_file() {
+    let root = TempDir::new("scan");
+    let nested = root.join("nested");
+    std::fs::create_dir_all(&nested).unwrap();
+    for i in 0..12 {
+        let dir: &std::path::Path = if i % 2 == 0 { &root } else { &nested };
+        std::fs::write(
+            dir.join(format!("f{i}.sail")),
+            format!("function f{i}() = {i}\n"),
+        )
+        .unwrap();
+    }
+    std::fs::write(root.join("notes.txt"), "not sail").unwrap();
+
+    let folder = Url::from_directory_path(&root).unwrap();
+    let files = scan_folders(std::iter::once(folder).collect(), &Default::default(), true);
+
+    assert_eq!(files.len(), 12);
+    for i in 0..12 {
+        let dir: &std::path::Path = if i % 2 == 0 { &root } else { &nested };
+        let url = Url::from_file_path(dir.join(format!("f{i}.sail"))).unwrap();
+        let file = files.get(&url).expect("scanned file");
+        assert!(file.definitions.contains_key(&format!("f{i}")));
+    }
+}
+
+#[test]
+fn symbol_index_dump_groups_locations_by_name() {
+    let a_uri = Url::parse("file:///a.sail").unwrap();
+    let b_uri = Url::parse("file:///b.sail").unwrap();
+    let a = File::new("val xlen_bytes : int\nlet xlen = 64\n".to_string());
+    let b = File::new("function xlen_bytes() = 8\nregister PC : bits(64)\n".to_string());
+    let files = [(&a_uri, &a), (&b_uri, &b)];
+
+    let dump = symbol_index_dump(files.iter().copied(), None);
+    let entries = dump["xlen_bytes"].as_array().expect("xlen_bytes entries");
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["uri"], "file:///a.sail");
+    assert_eq!(entries[0]["kind"], "value");
+    assert_eq!(entries[1]["uri"], "file:///b.sail");
+    assert_eq!(entries[1]["kind"], "function");
+    assert_eq!(entries[1]["range"]["start"]["character"], 9);
+    assert!(dump.get("PC").is_some());
+
+    let filtered = symbol_index_dump(files.iter().copied(), Some("XLEN"));
+    let names: Vec<_> = filtered.as_object().unwrap().keys().cloned().collect();
+    assert_eq!(names, vec!["xlen".to_string(), "xlen_bytes".to_string()]);
+}
+
+#[test]
+fn implicit_folder_of_lone_file_is_bounded() {
+    let root = TempDir::new("implicit");
+    let loose = root.join("loose");
+    std::fs::create_dir_all(loose.join("nested")).unwrap();
+    std::fs::write(loose.join("a.sail"), "function a() = 1\n").unwrap();
+    std::fs::write(loose.join("nested/b.sail"), "function b() = 2\n").unwrap();
+    let project = root.join("model");
+    std::fs::create_dir_all(project.join("src")).unwrap();
+    std::fs::write(
+        project.join("m.sail_project"),
+        "m {\n  files src/c.sail\n}\n",
+    )
+    .unwrap();
+    std::fs::write(project.join("src/c.sail"), "function c() = 3\n").unwrap();
+
+    // Outside any project only the file's own folder is scanned.
+    let mut files = crate::state::Files::default();
+    let uri = Url::from_file_path(loose.join("a.sail")).unwrap();
+    let folder = files.add_implicit_folder(&uri).unwrap();
+    assert_eq!(folder, Url::from_directory_path(&loose).unwrap());
+    let scanned = scan_folders(files.folders().clone(), files.shallow_folders(), false);
+    assert_eq!(scanned.keys().collect::<Vec<_>>(), vec![&uri]);
+
+    // Inside one the project folder stands in for the workspace.
+    let mut files = crate::state::Files::default();
+    let uri = Url::from_file_path(project.join("src/c.sail")).unwrap();
+    let folder = files.add_implicit_folder(&uri).unwrap();
+    assert_eq!(folder, Url::from_directory_path(&project).unwrap());
+    assert!(files.shallow_folders().is_empty());
+
+    assert_eq!(
+        files.add_implicit_folder(&Url::parse("untitled:Untitled-1").unwrap()),
+        None
+    );
+}
+
+#[test]
+fn tracks_uris_with_published_diagnostics() {
+    let uri = Url::parse("file:///a.sail").unwrap();
+    let file = File::new("function f() = {\n".to_string());
+    let diagnostics = file.lsp_diagnostics();
+    assert!(!diagnostics.is_empty());
+
+    let mut state = State::default();
+    state.record_published(&uri, &diagnostics);
+    assert!(state.published_diagnostics.contains(&uri));
+    state.record_published(&uri, &[]);
+    assert!(!state.published_diagnostics.contains(&uri));
+}
+
+#[test]
+fn library_files_are_left_out_of_edits() {
+    let library_uri = Url::parse("file:///opt/sail/lib/arith.sail").unwrap();
+    let user_uri = Url::parse("file:///w/main.sail").unwrap();
+    let user_source = "function add_int(x) = x\nfunction helper() = add_int(1)\n";
+    let mut state = State::default();
+    state
+        .disk_files
+        .set_library_folder(Url::parse("file:///opt/sail/lib/").unwrap());
+    state.disk_files.add_file(
+        library_uri.clone(),
+        File::new("function add_int(x) = x\n".to_string()),
+    );
+    state
+        .disk_files
+        .add_file(user_uri.clone(), File::new(user_source.to_string()));
+
+    let user_files = state.user_files().map(|(uri, _)| uri).collect::<Vec<_>>();
+    assert_eq!(user_files, [&user_uri]);
+
+    let file = state.get_file(&user_uri).unwrap();
+    let at = |needle: &str| file.source.position_at(user_source.find(needle).unwrap());
+    let shadowed = resolve_symbol_at(file, at("add_int(1)")).unwrap();
+    assert!(state.is_library_symbol(&user_uri, &shadowed));
+    let own = resolve_symbol_at(file, at("helper")).unwrap();
+    assert!(!state.is_library_symbol(&user_uri, &own));
+}
+
+#[test]
+fn reads_workspace_refresh_support_from_client_capabilities() {
+    let capabilities: tower_lsp::lsp_types::ClientCapabilities =
+        serde_json::from_value(serde_json::json!({
+            "workspace": {
+                "inlayHint": { "refreshSupport": true },
+                "codeLens": { "refreshSupport": false },
+                "diagnostic": { "refreshSupport": true }
+            }
+        }))
+        .unwrap();
+
+    assert_eq!(
+        RefreshSupport::from_capabilities(&capabilities),
+        RefreshSupport {
+            semantic_tokens: false,
+            inlay_hints: true,
+            code_lens: false,
+            diagnostics: true,
+        }
+    );
+    assert_eq!(
+        RefreshSupport::from_capabilities(&Default::default()),
+        RefreshSupport::default()
+    );
+}
+
+#[test]
+fn register_type_is_a_typed_binding() {
+    let file = File::new(
+        "bitfield Mstatus : bits(64) = { SD : 63 }\nregister mstatus : Mstatus\n".to_string(),
+    );
+    assert_eq!(
+        typed_bindings(&file).get("mstatus").map(String::as_str),
+        Some("Mstatus")
+    );
+}
+
+#[test]
+fn parses_transport_arguments() {
+    let parse = |args: &[&str]| {
+        crate::transport::Options::from_args(args.iter().map(|arg| arg.to_string()))
+            .map(|options| options.transport)
+    };
+
+    assert_eq!(parse(&[]), Ok(crate::transport::Transport::Stdio));
+    assert_eq!(
+        parse(&["--stdio", "--clientProcessId=42"]),
+        Ok(crate::transport::Transport::Stdio)
+    );
+    assert_eq!(
+        parse(&["--listen", "127.0.0.1:9257"]),
+        Ok(crate::transport::Transport::Listen(
+            "127.0.0.1:9257".to_string()
+        ))
+    );
+    assert_eq!(
+        parse(&["--pipe=/tmp/sail.sock"]),
+        Ok(crate::transport::Transport::Pipe("/tmp/sail.sock".into()))
+    );
+    assert!(parse(&["--listen"]).is_err());
+}
+
+#[test]
+fn parses_log_file_argument() {
+    let parse = |args: &[&str]| {
+        crate::transport::Options::from_args(args.iter().map(|arg| arg.to_string()))
+            .map(|options| options.log_file)
+    };
+
+    assert_eq!(parse(&["--stdio"]), Ok(None));
+    assert_eq!(
+        parse(&["--log-file", "/tmp/sail.log", "--stdio"]),
+        Ok(Some("/tmp/sail.log".into()))
+    );
+    assert_eq!(
+        parse(&["--listen=127.0.0.1:9257", "--log-file=sail.log"]),
+        Ok(Some("sail.log".into()))
+    );
+    assert!(parse(&["--log-file"]).is_err());
+}
+
+#[test]
+fn reads_file_list_from_sail_project() {
+    let project = r#"
+variable ARCH = RV64
+
+/* Core definitions.
+   prelude_old.sail is kept around but not built. */
+prelude {
+  files prelude.sail, "util/bits.sail" // helpers
+}
+
+model {
+  requires prelude
+  files
+    if $ARCH == RV32 then xlen32.sail else xlen64.sail,
+    $ARCH/extra.sail,
+    model.sail
+}
+"#;
+    assert_eq!(
+        project_file_list(project),
+        [
+            "prelude.sail",
+            "util/bits.sail",
+            "xlen32.sail",
+            "xlen64.sail",
+            "model.sail"
+        ]
+    );
+}
+
+#[test]
+fn workspace_scan_follows_sail_project() {
+    let root = TempDir::new("project");
+    std::fs::create_dir_all(root.join("src")).unwrap();
+    std::fs::write(
+        root.join("model.sail_project"),
+        "m {\n  files src/a.sail\n}\n",
+    )
+    .unwrap();
+    std::fs::write(root.join("src/a.sail"), "function a() = 1\n").unwrap();
+    std::fs::write(root.join("src/stray.sail"), "function a() = 2\n").unwrap();
+
+    let folder = Url::from_directory_path(&root).unwrap();
+    let files = scan_folders(std::iter::once(folder).collect(), &Default::default(), true);
+
+    assert_eq!(files.len(), 1);
+    let url = Url::from_file_path(root.join("src/a.sail")).unwrap();
+    assert!(files.contains_key(&url));
+}
+
+#[test]
+fn lazy_index_scans_only_the_projects_of_opened_files() {
+    let root = TempDir::new("lazy");
+    std::fs::create_dir_all(root.join("plain")).unwrap();
+    std::fs::create_dir_all(root.join("model/src")).unwrap();
+    std::fs::write(
+        root.join("model/m.sail_project"),
+        "m {\n  files src/a.sail\n}\n",
+    )
+    .unwrap();
+    let url = |path: &str| Url::from_file_path(root.join(path)).unwrap();
+    let dir = |path: &str| Url::from_directory_path(root.join(path)).unwrap();
+
+    let mut files = crate::state::Files::default();
+    files.add_folder(dir(""));
+    assert!(files.add_opened_root(&url("model/src/a.sail")));
+    assert!(files.add_opened_root(&url("plain/b.sail")));
+    assert!(!files.add_opened_root(&url("model/src/c.sail")));
+
+    let expected = [dir("model"), dir("plain")].into_iter().collect();
+    assert_eq!(files.scan_roots(false, true), expected);
+    assert_eq!(
+        files.scan_roots(false, false),
+        [dir("")].into_iter().collect()
+    );
+}
+
+#[test]
+fn folder_membership_respects_path_boundaries() {
+    let mut files = crate::state::Files::default();
+    files.add_folder(Url::parse("file:///w/proj").unwrap());
+    let inside = |uri: &str| files.is_in_folders(&Url::parse(uri).unwrap());
+    assert!(inside("file:///w/proj/x.sail"));
+    assert!(inside("file:///w/proj/nested/y.sail"));
+    assert!(!inside("file:///w/proj-old/x.sail"));
+    assert!(!inside("untitled:Untitled-1"));
+}
+
+#[cfg(unix)]
+#[test]
+fn workspace_scan_follows_symlinked_directories_when_enabled() {
+    let base = TempDir::new("symlink");
+    let shared = base.join("shared");
+    let root = base.join("model");
+    std::fs::create_dir_all(&shared).unwrap();
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(shared.join("defs.sail"), "function d() = 1\n").unwrap();
+    std::os::unix::fs::symlink(&shared, root.join("shared")).unwrap();
+
+    let folder = Url::from_directory_path(&root).unwrap();
+    let followed = scan_folders(
+        std::iter::once(folder.clone()).collect(),
+        &Default::default(),
+        true,
+    );
+    let not_followed = scan_folders(
+        std::iter::once(folder).collect(),
+        &Default::default(),
+        false,
+    );
 
+    // Linked files keep the path under the workspace folder.
+    let url = Url::from_file_path(root.join("shared/defs.sail")).unwrap();
+    assert_eq!(followed.keys().collect::<Vec<_>>(), [&url]);
+    assert!(not_followed.is_empty());
+}
+
+#[test]
+fn finds_sail_in_an_opam_switch() {
+    let home = TempDir::new("home");
+    let bin = home.join(".opam/default/bin");
+    std::fs::create_dir_all(&bin).unwrap();
+    let sail = bin.join(format!("sail{}", std::env::consts::EXE_SUFFIX));
+    std::fs::write(&sail, "").unwrap();
+
+    let empty_path = std::env::temp_dir().join(format!("sail-path-{}", std::process::id()));
+    let found = find_sail_binary(Some(empty_path.into_os_string()), Some(&home));
+
+    assert_eq!(found, Some(sail));
+}
+
+#[tokio::test]
+async fn type_of_params_are_typed() {
+    use crate::backend::{Backend, TypeOfParams};
+
+    let params = serde_json::json!({
+        "textDocument": { "uri": "untitled:missing.sail" },
+        "range": {
+            "start": { "line": 0, "character": 0 },
+            "end": { "line": 0, "character": 1 },
+        },
+    });
+    let params: TypeOfParams = serde_json::from_value(params).unwrap();
+    assert_eq!(params.range.end.character, 1);
+    let (service, _) = tower_lsp::LspService::new(Backend::new_with_client);
+    assert_eq!(service.inner().type_of(params).await.unwrap(), None);
+
+    let malformed = serde_json::json!({ "textDocument": { "uri": "untitled:a.sail" } });
+    assert!(serde_json::from_value::<TypeOfParams>(malformed).is_err());
+}
+
+#[tokio::test]
+async fn interleaved_document_edits_apply_in_order() {
+    use crate::backend::Backend;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::Poll;
+    use tower_lsp::lsp_types::{
+        DidChangeTextDocumentParams, DidOpenTextDocumentParams, Position,
+        TextDocumentContentChangeEvent, TextDocumentItem, VersionedTextDocumentIdentifier,
+    };
+    use tower_lsp::LanguageServer;
+
+    // Without a reader on the client socket, log messages would block, so
+    // drop it and let them fail straight away.
+    let (service, _) = tower_lsp::LspService::new(Backend::new_with_client);
+    let backend = service.inner();
+    // Untitled, so there is no folder to scan.
+    let uri = Url::parse("untitled:edits.sail").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri.clone(), "sail".to_string(), 0, String::new()),
+        })
+        .await;
+
+    // Each edit appends at the end of the text left by the previous one, so
+    // any reordering shows up in the final text. Poll them all together, as
+    // tower-lsp does with concurrent notifications.
+    let mut expected = String::new();
+    let mut pending: Vec<Pin<Box<dyn Future<Output = ()> + '_>>> = Vec::new();
+    for version in 1..=200 {
+        let end = Position::new(0, expected.len() as u32);
+        let text = format!("{version},");
+        expected.push_str(&text);
+        pending.push(Box::pin(backend.did_change(DidChangeTextDocumentParams {
+            text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+            content_changes: vec![TextDocumentContentChangeEvent {
+                range: Some(Range::new(end, end)),
+                range_length: None,
+                text,
+            }],
+        })));
+    }
+    std::future::poll_fn(|cx| {
+        pending.retain_mut(|edit| edit.as_mut().poll(cx).is_pending());
+        if pending.is_empty() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    })
+    .await;
+
+    let state = backend.state.read().await;
+    assert_eq!(state.open_files[&uri].source.text(), expected);
+    assert_eq!(state.diagnostic_versions.get(&uri), Some(&200));
+}
+
+#[tokio::test]
+async fn rejected_edit_batch_stops_range_edits_until_full_text() {
+    use crate::backend::Backend;
+    use tower_lsp::lsp_types::{
+        DidChangeTextDocumentParams, DidOpenTextDocumentParams, Position,
+        TextDocumentContentChangeEvent, TextDocumentItem, VersionedTextDocumentIdentifier,
+    };
+    use tower_lsp::LanguageServer;
+
+    let (service, _) = tower_lsp::LspService::new(Backend::new_with_client);
+    let backend = service.inner();
+    let uri = Url::parse("untitled:sync.sail").unwrap();
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(uri.clone(), "sail".to_string(), 0, "let".into()),
+        })
+        .await;
+    let change = |version, range: Option<Range>, text: &str| DidChangeTextDocumentParams {
+        text_document: VersionedTextDocumentIdentifier::new(uri.clone(), version),
+        content_changes: vec![TextDocumentContentChangeEvent {
+            range,
+            range_length: None,
+            text: text.to_string(),
+        }],
+    };
+
+    let inverted = Range::new(Position::new(0, 2), Position::new(0, 1));
+    backend.did_change(change(1, Some(inverted), "x")).await;
+    // The client applied that batch somehow, so this edit addresses text the
+    // server does not have.
+    let start = Range::new(Position::new(0, 0), Position::new(0, 0));
+    backend.did_change(change(2, Some(start), "y")).await;
+    {
+        let state = backend.state.read().await;
+        assert_eq!(state.open_files[&uri].source.text(), "let");
+        assert_eq!(state.diagnostic_versions.get(&uri), Some(&0));
+        assert_eq!(state.open_file_diagnostics(&uri, None), Some(Vec::new()));
+    }
+
+    backend.did_change(change(3, None, "let x = 1")).await;
+    backend.did_change(change(4, Some(start), "  ")).await;
+    let state = backend.state.read().await;
+    assert_eq!(state.open_files[&uri].source.text(), "  let x = 1");
+    assert_eq!(state.diagnostic_versions.get(&uri), Some(&4));
+    assert!(!state.out_of_sync.contains(&uri));
+}
+
+#[tokio::test]
+async fn deleting_a_file_republishes_open_diagnostics() {
+    use crate::backend::Backend;
+    use tower_lsp::lsp_types::{
+        DidChangeWatchedFilesParams, DidOpenTextDocumentParams, FileChangeType, FileEvent,
+        TextDocumentItem,
+    };
+    use tower_lsp::LanguageServer;
+
+    let (service, _) = tower_lsp::LspService::new(Backend::new_with_client);
+    let backend = service.inner();
+    let uri = Url::parse("untitled:main.sail").unwrap();
+    let other = Url::parse("file:///nonexistent/other.sail").unwrap();
+    let source = "function foo() = 1\n";
+    backend
+        .state
+        .write()
+        .await
+        .disk_files
+        .add_file(other.clone(), File::new(source.to_string()));
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                uri.clone(),
+                "sail".to_string(),
+                0,
+                source.to_string(),
+            ),
+        })
+        .await;
+    {
+        let mut state = backend.state.write().await;
+        let index = state.definition_index();
+        let diagnostics = state.open_file_diagnostics(&uri, index.as_ref()).unwrap();
+        assert!(!diagnostics.is_empty(), "duplicate of other.sail");
+        state.record_published(&uri, &diagnostics);
+    }
+
+    backend
+        .did_change_watched_files(DidChangeWatchedFilesParams {
+            changes: vec![FileEvent::new(other.clone(), FileChangeType::DELETED)],
+        })
+        .await;
+
+    let state = backend.state.read().await;
+    assert!(state.get_file(&other).is_none());
+    assert!(!state.published_diagnostics.contains(&uri));
+}
+
+#[tokio::test]
+async fn completes_a_saved_file_before_it_is_opened() {
+    use crate::backend::Backend;
+    use tower_lsp::lsp_types::{
+        CompletionParams, CompletionResponse, PartialResultParams, Position,
+        TextDocumentIdentifier, TextDocumentPositionParams, WorkDoneProgressParams,
+    };
+    use tower_lsp::LanguageServer;
+
+    let dir = TempDir::new("lsp-unopened");
+    let path = dir.join("main.sail");
+    std::fs::write(&path, "function helper() = 1\nfunction main() = hel\n").unwrap();
+    let uri = Url::from_file_path(&path).unwrap();
+
+    let (service, _) = tower_lsp::LspService::new(Backend::new_with_client);
+    let response = service
+        .inner()
+        .completion(CompletionParams {
+            text_document_position: TextDocumentPositionParams::new(
+                TextDocumentIdentifier::new(uri),
+                Position::new(1, 21),
+            ),
+            work_done_progress_params: WorkDoneProgressParams::default(),
+            partial_result_params: PartialResultParams::default(),
+            context: None,
+        })
+        .await
+        .unwrap();
+
+    let Some(CompletionResponse::Array(items)) = response else {
+        panic!("expected completion items, got {response:?}");
+    };
+    assert_eq!(items[0].label, "helper");
+}
+
+#[tokio::test]
+async fn reindex_request_reports_scan_stats() {
+    use crate::backend::Backend;
+
+    let root = TempDir::new("reindex");
+    std::fs::write(root.join("a.sail"), "function a() = 1\nfunction b() = 2\n").unwrap();
+    std::fs::write(root.join("c.sail"), "register c : int\n").unwrap();
+
+    let (service, _) = tower_lsp::LspService::new(Backend::new_with_client);
+    let backend = service.inner();
+    backend
+        .state
+        .write()
+        .await
+        .disk_files
+        .add_folder(Url::from_directory_path(&root).unwrap());
+    let stats = backend.reindex(serde_json::Value::Null).await.unwrap();
+
+    assert_eq!(stats["files"], 2);
+    assert_eq!(stats["symbols"], 3);
+    assert!(stats["elapsedMs"].is_u64());
+}
+
+#[tokio::test]
+async fn enabling_lazy_index_keeps_already_opened_projects() {
+    use crate::backend::Backend;
+    use tower_lsp::lsp_types::{
+        DidChangeConfigurationParams, DidOpenTextDocumentParams, TextDocumentItem,
+    };
+    use tower_lsp::LanguageServer;
+
+    let root = TempDir::new("lazy-toggle");
+    std::fs::create_dir_all(root.join("open")).unwrap();
+    std::fs::create_dir_all(root.join("other")).unwrap();
+    std::fs::write(root.join("open/a.sail"), "function a() = 1\n").unwrap();
+    std::fs::write(root.join("open/b.sail"), "function b() = 2\n").unwrap();
+    std::fs::write(root.join("other/c.sail"), "function c() = 3\n").unwrap();
+    let url = |path: &str| Url::from_file_path(root.join(path)).unwrap();
+
+    let (service, _) = tower_lsp::LspService::new(Backend::new_with_client);
+    let backend = service.inner();
+    backend
+        .state
+        .write()
+        .await
+        .disk_files
+        .add_folder(Url::from_directory_path(&root).unwrap());
+    backend
+        .did_open(DidOpenTextDocumentParams {
+            text_document: TextDocumentItem::new(
+                url("open/a.sail"),
+                "sail".to_string(),
+                0,
+                "function a() = 1\n".to_string(),
+            ),
+        })
+        .await;
+    backend
+        .did_change_configuration(DidChangeConfigurationParams {
+            settings: serde_json::json!({ "sail": { "files": { "lazyIndex": true } } }),
+        })
+        .await;
+    backend.reindex(serde_json::Value::Null).await.unwrap();
+
+    let state = backend.state.read().await;
+    assert!(state.disk_files.get_file(&url("open/b.sail")).is_some());
+    assert!(state.disk_files.get_file(&url("other/c.sail")).is_none());
+}
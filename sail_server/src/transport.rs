@@ -0,0 +1,102 @@
+use std::path::PathBuf;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tower_lsp::{ClientSocket, LspService, Server};
+
+use crate::backend::Backend;
+
+/// How the server talks to its client, chosen on the command line.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Transport {
+    /// `--stdio` (the default).
+    Stdio,
+    /// `--listen <addr>`: accept one client over TCP.
+    Listen(String),
+    /// `--pipe <path>`: connect to a Unix socket or Windows named pipe.
+    Pipe(PathBuf),
+}
+
+/// What the command line asks for.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Options {
+    pub(crate) transport: Transport,
+    /// `--log-file <path>`: where to write the server log instead of stderr.
+    pub(crate) log_file: Option<PathBuf>,
+}
+
+impl Options {
+    /// Parse command-line arguments (without the program name). Unrelated
+    /// arguments, such as `--clientProcessId`, are ignored.
+    pub(crate) fn from_args<I>(args: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = String>,
+    {
+        let mut transport = Transport::Stdio;
+        let mut log_file = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            let mut value = || {
+                inline_value
+                    .clone()
+                    .or_else(|| args.next())
+                    .ok_or_else(|| format!("{flag} needs a value"))
+            };
+            match flag.as_str() {
+                "--stdio" => transport = Transport::Stdio,
+                "--listen" => transport = Transport::Listen(value()?),
+                "--pipe" => transport = Transport::Pipe(PathBuf::from(value()?)),
+                "--log-file" => log_file = Some(PathBuf::from(value()?)),
+                _ => {}
+            }
+        }
+        Ok(Options {
+            transport,
+            log_file,
+        })
+    }
+}
+
+impl Transport {
+    pub(crate) async fn serve(
+        self,
+        service: LspService<Backend>,
+        socket: ClientSocket,
+    ) -> std::io::Result<()> {
+        match self {
+            Transport::Stdio => {
+                serve_streams(tokio::io::stdin(), tokio::io::stdout(), service, socket).await
+            }
+            Transport::Listen(addr) => {
+                let listener = tokio::net::TcpListener::bind(&addr).await?;
+                let (stream, _) = listener.accept().await?;
+                let (read, write) = stream.into_split();
+                serve_streams(read, write, service, socket).await
+            }
+            Transport::Pipe(path) => {
+                #[cfg(unix)]
+                let stream = tokio::net::UnixStream::connect(&path).await?;
+                #[cfg(windows)]
+                let stream = tokio::net::windows::named_pipe::ClientOptions::new().open(&path)?;
+                let (read, write) = tokio::io::split(stream);
+                serve_streams(read, write, service, socket).await
+            }
+        }
+    }
+}
+
+async fn serve_streams<R, W>(
+    read: R,
+    write: W,
+    service: LspService<Backend>,
+    socket: ClientSocket,
+) -> std::io::Result<()>
+where
+    R: AsyncRead + Unpin,
+    W: AsyncWrite,
+{
+    Server::new(read, write, socket).serve(service).await;
+    Ok(())
+}
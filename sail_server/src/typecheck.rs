@@ -3,6 +3,7 @@ use std::collections::{HashMap, HashSet};
 use crate::diagnostics::reporting::{diagnostic_for_error, Error as ReportingError};
 use crate::diagnostics::type_error::{TypeError, VectorOrder};
 use crate::diagnostics::{Diagnostic, DiagnosticCode};
+use crate::logging::server_log;
 use crate::state::File;
 use crate::symbols::collect_callable_signatures;
 use sail_parser::{
@@ -3193,13 +3194,13 @@ impl<'a> Checker<'a> {
         }
         if let Some(span) = span {
             let pos = self.file.source.position_at(span.start);
-            eprintln!(
+            server_log!(
                 "[typecheck] {kind} {name} @ {}:{}",
                 pos.line + 1,
                 pos.character + 1
             );
         } else {
-            eprintln!("[typecheck] {kind} {name}");
+            server_log!("[typecheck] {kind} {name}");
         }
     }
 